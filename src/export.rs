@@ -0,0 +1,16 @@
+pub mod abc;
+pub mod midi;
+
+use std::path::Path;
+
+pub fn save_file<C, P>(contents: C, path: P) -> Result<(), anyhow::Error>
+where
+    C: AsRef<[u8]>,
+    P: AsRef<Path>,
+{
+    if let Some(parent) = path.as_ref().parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    std::fs::write(path, contents)?;
+    Ok(())
+}
@@ -0,0 +1,550 @@
+use std::collections::HashMap;
+use std::fmt::Write;
+use std::path::Path;
+
+use crate::score::{Bar, Dynamic, Key, Mode, Part, Rational, Score, Tuplet};
+
+use super::save_file;
+
+const LETTERS: [(char, i32); 7] = [
+    ('C', 0),
+    ('D', 2),
+    ('E', 4),
+    ('F', 5),
+    ('G', 7),
+    ('A', 9),
+    ('B', 11),
+];
+
+struct Spelling {
+    tonic: usize,
+    signature: [i32; 7],
+    classes: [(usize, i32); 12],
+}
+
+impl Spelling {
+    fn new(key: &Key) -> Self {
+        let signature = |tonic: usize| {
+            let mut signature = [0; 7];
+            for degree in 0..7 {
+                let letter = (tonic + degree as usize) % 7;
+                let raised = key.mode() == Mode::Minor && degree == 6;
+                let class = key.pitch_class(degree) as i32 - raised as i32;
+                signature[letter] = (class - LETTERS[letter].1 + 6).rem_euclid(12) - 6;
+            }
+            signature
+        };
+        let (tonic, signature) = (0..7)
+            .map(|x| (x, signature(x)))
+            .filter(|x| x.1.iter().all(|y| y.abs() <= 1))
+            .min_by_key(|x| x.1.iter().map(|y| y.abs()).sum::<i32>())
+            .unwrap_or((0, [0; 7]));
+
+        let mut classes = [None; 12];
+        for (letter, &accidental) in signature.iter().enumerate() {
+            let class = (LETTERS[letter].1 + accidental).rem_euclid(12) as usize;
+            classes[class] = Some((letter, accidental));
+        }
+        let classes = std::array::from_fn(|class| {
+            classes[class]
+                .or(classes[(class + 11) % 12].map(|x| (x.0, x.1 + 1)))
+                .or(classes[(class + 1) % 12].map(|x| (x.0, x.1 - 1)))
+                .unwrap_or((0, 0))
+        });
+        Self {
+            tonic,
+            signature,
+            classes,
+        }
+    }
+    fn name(&self, mode: Mode) -> String {
+        let accidental = match self.signature[self.tonic] {
+            1 => "#",
+            -1 => "b",
+            _ => "",
+        };
+        let mode = match mode {
+            Mode::Major => "",
+            Mode::Minor => "m",
+            Mode::Dorian => "dor",
+            Mode::Phrygian => "phr",
+            Mode::Lydian => "lyd",
+            Mode::Mixolydian => "mix",
+        };
+        format!("{}{}{}", LETTERS[self.tonic].0, accidental, mode)
+    }
+}
+
+#[derive(Debug, Clone)]
+struct Element {
+    start: Rational,
+    length: Rational,
+    pitches: Vec<(i32, bool)>,
+    accent: bool,
+}
+
+impl Element {
+    fn end(&self) -> Rational {
+        self.start + self.length
+    }
+    fn straight(&self) -> bool {
+        [self.start, self.length]
+            .iter()
+            .all(|x| (x.den() as u64).is_power_of_two())
+    }
+    fn fits_after(&self, other: &Element) -> bool {
+        self.end() <= other.start
+            && (self.straight() == other.straight()
+                || self.end() <= Rational::int(other.start.floor()))
+    }
+    fn ties_into(&self, other: &Element) -> bool {
+        self.end() == other.start
+            && self
+                .pitches
+                .iter()
+                .any(|(x, tie)| *tie && other.pitches.iter().any(|(y, _)| x == y))
+    }
+    fn slice(&self, from: Rational, to: Rational) -> Option<Self> {
+        let start = self.start.max(from);
+        let end = self.end().min(to);
+        if start >= end {
+            return None;
+        }
+        let cut = self.end() > to;
+        Some(Self {
+            start: start - from,
+            length: end - start,
+            pitches: self
+                .pitches
+                .iter()
+                .map(|&(x, tie)| (x, tie || cut))
+                .collect(),
+            accent: self.accent && start == self.start,
+        })
+    }
+}
+
+struct Measure<'a, const N: usize> {
+    bar: &'a Bar<N>,
+    open: String,
+    close: String,
+}
+
+pub fn save_abc<const N: usize, P>(
+    score: &Score<N>,
+    title: &str,
+    names: [&str; N],
+    path: P,
+) -> Result<(), anyhow::Error>
+where
+    P: AsRef<Path>,
+{
+    save_file(write_abc(score, title, names), path)
+}
+
+pub fn write_abc<const N: usize>(score: &Score<N>, title: &str, names: [&str; N]) -> String {
+    let measures = layout(score);
+    let bars: Vec<&Bar<N>> = measures.iter().map(|x| x.bar).collect();
+    let mut out = String::new();
+
+    writeln!(out, "X:1").unwrap();
+    writeln!(out, "T:{}", title).unwrap();
+    if let Some(bar) = bars.first() {
+        writeln!(out, "M:{}/4", bar.beats).unwrap();
+        writeln!(out, "L:1/4").unwrap();
+        writeln!(out, "Q:1/4={}", bar.bpm.round()).unwrap();
+    }
+    match bars.first() {
+        Some(bar) => writeln!(out, "K:{}", key_name(&bar.key)).unwrap(),
+        None => writeln!(out, "K:C").unwrap(),
+    }
+
+    let starts: Vec<Rational> = bars
+        .iter()
+        .scan(Rational::ZERO, |time, bar| {
+            let start = *time;
+            *time += bar.length();
+            Some(start)
+        })
+        .collect();
+
+    for (voice, name) in names.iter().enumerate() {
+        let lanes = split_lanes(&bars, &starts, voice);
+        let percussion = bars
+            .iter()
+            .any(|bar| bar.notes[voice].iter().any(|x| x.1.drum.is_some()));
+
+        for lane in 0..lanes.len().max(1) {
+            if percussion {
+                writeln!(
+                    out,
+                    "V:{}_{} name=\"{}\" clef=perc",
+                    voice + 1,
+                    lane + 1,
+                    name
+                )
+                .unwrap();
+                writeln!(out, "%%MIDI channel 10").unwrap();
+            } else {
+                writeln!(out, "V:{}_{} name=\"{}\"", voice + 1, lane + 1, name).unwrap();
+            }
+            let mut previous: Option<&Bar<N>> = None;
+            for (i, (bar, measure)) in bars.iter().zip(measures.iter()).enumerate() {
+                out.push_str(&measure.open);
+                write_fields(&mut out, bar, previous, lane == 0);
+                if let Some(chord) = bar.chord.as_ref().filter(|_| voice == 0 && lane == 0) {
+                    write!(out, "\"^{}\"", chord.numeral(&bar.key)).unwrap();
+                }
+                let chords: Vec<Element> = lanes
+                    .get(lane)
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|x| x.slice(starts[i], starts[i] + bar.length()))
+                    .collect();
+                write_bar(&mut out, bar, &bar.tuplets[voice], &chords);
+                write!(out, " {}", measure.close).unwrap();
+                if (i + 1) % 4 == 0 || i + 1 == bars.len() {
+                    out.push('\n');
+                }
+                previous = Some(bar);
+            }
+        }
+    }
+    out
+}
+
+fn layout<const N: usize>(score: &Score<N>) -> Vec<Measure<'_, N>> {
+    let mut measures: Vec<Measure<N>> = Vec::new();
+    for part in score.parts() {
+        let repeated = part.repeats > 1;
+        if repeated {
+            if let Some(last) = measures.last_mut() {
+                last.close = if last.close == ":|" { "::" } else { "|:" }.to_string();
+            }
+        }
+
+        let first = measures.len();
+        push_sections(&mut measures, score, &part.sections);
+        if let Some(measure) = measures.get_mut(first) {
+            if repeated && first == 0 {
+                measure.open.push_str("|:");
+            }
+            if let Some(mark) = &part.mark {
+                write!(measure.open, "[P:{}]", mark).unwrap();
+            }
+            if part.repeats > 2 {
+                write!(measure.open, "\"^x{}\"", part.repeats).unwrap();
+            }
+        }
+
+        if part.endings.is_empty() {
+            if let Some(last) = measures.last_mut().filter(|_| repeated) {
+                last.close = ":|".to_string();
+            }
+        }
+        for (k, ending) in part.endings.iter().enumerate() {
+            let start = measures.len();
+            push_sections(&mut measures, score, ending);
+            if let Some(measure) = measures.get_mut(start) {
+                measure.open = format!("[{} ", ending_passes(part, k));
+            }
+            if k + 1 < part.endings.len() {
+                if let Some(last) = measures.last_mut() {
+                    last.close = ":|".to_string();
+                }
+            }
+        }
+
+        if let Some(last) = measures.last_mut().filter(|x| x.close == "|") {
+            last.close = "||".to_string();
+        }
+    }
+    if let Some(last) = measures.last_mut().filter(|x| x.close == "||") {
+        last.close = "|]".to_string();
+    }
+    measures
+}
+
+fn push_sections<'a, const N: usize>(
+    measures: &mut Vec<Measure<'a, N>>,
+    score: &'a Score<N>,
+    sections: &[String],
+) {
+    let bars = sections
+        .iter()
+        .filter_map(|x| score.section(x))
+        .flat_map(|x| x.bars());
+    measures.extend(bars.map(|bar| Measure {
+        bar,
+        open: String::new(),
+        close: "|".to_string(),
+    }));
+}
+
+fn ending_passes(part: &Part, ending: usize) -> String {
+    let passes: Vec<String> = (0..part.repeats)
+        .filter(|&x| part.ending(x) == Some(ending))
+        .map(|x| (x + 1).to_string())
+        .collect();
+    passes.join(",")
+}
+
+fn write_fields<const N: usize>(
+    out: &mut String,
+    bar: &Bar<N>,
+    previous: Option<&Bar<N>>,
+    dynamics: bool,
+) {
+    if let Some(previous) = previous {
+        if previous.beats != bar.beats {
+            write!(out, "[M:{}/4]", bar.beats).unwrap();
+        }
+        if previous.bpm != bar.bpm {
+            write!(out, "[Q:1/4={}]", bar.bpm.round()).unwrap();
+        }
+        if previous.key != bar.key {
+            write!(out, "[K:{}]", key_name(&bar.key)).unwrap();
+        }
+    }
+    if dynamics && previous.is_none_or(|x| x.dynamic != bar.dynamic) {
+        write!(out, "!{}!", dynamic_mark(bar.dynamic)).unwrap();
+    }
+}
+
+fn key_name(key: &Key) -> String {
+    Spelling::new(key).name(key.mode())
+}
+
+fn dynamic_mark(dynamic: Dynamic) -> &'static str {
+    match dynamic {
+        Dynamic::Piano => "p",
+        Dynamic::MezzoPiano => "mp",
+        Dynamic::MezzoForte => "mf",
+        Dynamic::Forte => "f",
+    }
+}
+
+fn split_lanes<const N: usize>(
+    bars: &[&Bar<N>],
+    starts: &[Rational],
+    voice: usize,
+) -> Vec<Vec<Element>> {
+    let mut chords: Vec<Element> = Vec::new();
+    for (bar, bar_start) in bars.iter().zip(starts) {
+        for (offset, note) in bar.notes[voice].iter() {
+            let start = *bar_start + *offset;
+            let pitch = (note.midi(&bar.key) as i32, note.tie);
+            match chords
+                .iter_mut()
+                .find(|x| x.start == start && x.length == note.length)
+            {
+                Some(chord) => {
+                    chord.pitches.push(pitch);
+                    chord.accent |= note.accent;
+                }
+                None => chords.push(Element {
+                    start,
+                    length: note.length,
+                    pitches: vec![pitch],
+                    accent: note.accent,
+                }),
+            }
+        }
+    }
+    chords.sort_by_key(|x| x.start);
+
+    let mut lanes: Vec<Vec<Element>> = Vec::new();
+    for mut chord in chords {
+        chord.pitches.sort();
+        chord.pitches.dedup_by(|a, b| {
+            if a.0 == b.0 {
+                b.1 |= a.1;
+                return true;
+            }
+            false
+        });
+        let lane = lanes
+            .iter()
+            .position(|lane| lane.last().is_some_and(|x| x.ties_into(&chord)))
+            .or_else(|| {
+                lanes
+                    .iter()
+                    .position(|lane| lane.last().is_none_or(|x| x.fits_after(&chord)))
+            });
+        match lane {
+            Some(i) => lanes[i].push(chord),
+            None => lanes.push(vec![chord]),
+        }
+    }
+    lanes
+}
+
+fn write_bar<const N: usize>(
+    out: &mut String,
+    bar: &Bar<N>,
+    tuplets: &[Tuplet],
+    chords: &[Element],
+) {
+    let mut boundaries: Vec<Rational> = tuplets.iter().flat_map(|x| [x.start, x.end()]).collect();
+    boundaries.push(bar.length());
+
+    let mut elements = Vec::new();
+    let mut cursor = Rational::ZERO;
+    for chord in chords {
+        push_rests(&mut elements, cursor, chord.start, &boundaries);
+        elements.push(chord.clone());
+        cursor = chord.end();
+    }
+    push_rests(&mut elements, cursor, bar.length(), &boundaries);
+
+    let spelling = Spelling::new(&bar.key);
+    let mut accidentals = HashMap::new();
+    let mut i = 0;
+    while i < elements.len() {
+        let tuplet = tuplets.iter().find(|x| {
+            x.start == elements[i].start
+                && elements[i].end() <= x.end()
+                && elements[i..]
+                    .iter()
+                    .take_while(|y| y.start < x.end())
+                    .all(|y| fits_tuplet(x, y))
+        });
+        match tuplet {
+            Some(tuplet) => {
+                let count = elements[i..]
+                    .iter()
+                    .take_while(|x| x.end() <= tuplet.end())
+                    .count();
+                let scale = Rational::new(tuplet.count as i64, tuplet.normal() as i64);
+                write!(out, " ({}:{}:{}", tuplet.count, tuplet.normal(), count).unwrap();
+                for element in elements[i..i + count].iter() {
+                    write_element(out, element, scale, &spelling, &mut accidentals);
+                }
+                i += count;
+            }
+            None => {
+                write_element(
+                    out,
+                    &elements[i],
+                    Rational::ONE,
+                    &spelling,
+                    &mut accidentals,
+                );
+                i += 1;
+            }
+        }
+    }
+}
+
+fn fits_tuplet(tuplet: &Tuplet, element: &Element) -> bool {
+    let unit = tuplet.unit();
+    element.end() <= tuplet.end()
+        && ((element.start - tuplet.start) / unit).fract() == Rational::ZERO
+        && (element.length / unit).fract() == Rational::ZERO
+}
+
+fn push_rests(elements: &mut Vec<Element>, from: Rational, to: Rational, boundaries: &[Rational]) {
+    let mut points: Vec<Rational> = boundaries
+        .iter()
+        .copied()
+        .filter(|x| *x > from && *x < to)
+        .collect();
+    points.sort();
+    points.dedup();
+    points.push(to);
+
+    let mut start = from;
+    for point in points {
+        if point > start {
+            elements.push(Element {
+                start,
+                length: point - start,
+                pitches: Vec::new(),
+                accent: false,
+            });
+        }
+        start = point;
+    }
+}
+
+fn write_element(
+    out: &mut String,
+    element: &Element,
+    scale: Rational,
+    spelling: &Spelling,
+    accidentals: &mut HashMap<(usize, i32), i32>,
+) {
+    out.push(' ');
+    if element.accent {
+        out.push_str("!>!");
+    }
+    match element.pitches.as_slice() {
+        [] => {
+            out.push('z');
+            write_length(out, element.length * scale);
+        }
+        [(pitch, tie)] => {
+            write_pitch(out, *pitch, spelling, accidentals);
+            write_length(out, element.length * scale);
+            if *tie {
+                out.push('-');
+            }
+        }
+        pitches => {
+            out.push('[');
+            for (pitch, tie) in pitches {
+                write_pitch(out, *pitch, spelling, accidentals);
+                if *tie {
+                    out.push('-');
+                }
+            }
+            out.push(']');
+            write_length(out, element.length * scale);
+        }
+    }
+}
+
+fn write_pitch(
+    out: &mut String,
+    pitch: i32,
+    spelling: &Spelling,
+    accidentals: &mut HashMap<(usize, i32), i32>,
+) {
+    let (index, accidental) = spelling.classes[pitch.rem_euclid(12) as usize];
+    let (letter, natural) = LETTERS[index];
+    let octave = (pitch - accidental - natural).div_euclid(12) - 1;
+    let current = accidentals
+        .get(&(index, octave))
+        .copied()
+        .unwrap_or(spelling.signature[index]);
+    if accidental != current {
+        match accidental {
+            2 => out.push_str("^^"),
+            1 => out.push('^'),
+            0 => out.push('='),
+            -1 => out.push('_'),
+            _ => out.push_str("__"),
+        }
+    }
+    accidentals.insert((index, octave), accidental);
+
+    if octave >= 5 {
+        out.push(letter.to_ascii_lowercase());
+        (5..octave).for_each(|_| out.push('\''));
+    } else {
+        out.push(letter);
+        (octave..4).for_each(|_| out.push(','));
+    }
+}
+
+fn write_length(out: &mut String, length: Rational) {
+    if length == Rational::ONE {
+        return;
+    }
+    if length.num() != 1 {
+        write!(out, "{}", length.num()).unwrap();
+    }
+    if length.den() != 1 {
+        write!(out, "/{}", length.den()).unwrap();
+    }
+}
@@ -0,0 +1,174 @@
+use std::path::Path;
+
+use crate::score::{Bar, Rational, Score};
+
+use super::save_file;
+
+const TICKS_PER_BEAT: i64 = 480;
+const DRUM_CHANNEL: u8 = 9;
+
+#[derive(Debug, Clone)]
+struct Event {
+    start: Rational,
+    length: Rational,
+    channel: u8,
+    pitch: u8,
+    velocity: u8,
+    tie: bool,
+}
+
+pub fn save_midi<const N: usize, P>(
+    score: &Score<N>,
+    names: [&str; N],
+    path: P,
+) -> Result<(), anyhow::Error>
+where
+    P: AsRef<Path>,
+{
+    save_file(write_midi(score, names), path)
+}
+
+pub fn write_midi<const N: usize>(score: &Score<N>, names: [&str; N]) -> Vec<u8> {
+    let bars: Vec<&Bar<N>> = score.expand().iter().flat_map(|x| x.1.bars()).collect();
+    let starts: Vec<Rational> = bars
+        .iter()
+        .scan(Rational::ZERO, |time, bar| {
+            let start = *time;
+            *time += bar.length();
+            Some(start)
+        })
+        .collect();
+
+    let mut tracks = vec![tempo_track(&bars, &starts)];
+    for (voice, name) in names.iter().enumerate() {
+        let channel = if voice as u8 >= DRUM_CHANNEL {
+            voice as u8 + 1
+        } else {
+            voice as u8
+        } % 16;
+        let events = merge_ties(collect_events(&bars, &starts, voice, channel));
+        tracks.push(note_track(name, &events));
+    }
+
+    let mut out = Vec::new();
+    out.extend_from_slice(b"MThd");
+    out.extend_from_slice(&6u32.to_be_bytes());
+    out.extend_from_slice(&1u16.to_be_bytes());
+    out.extend_from_slice(&(tracks.len() as u16).to_be_bytes());
+    out.extend_from_slice(&(TICKS_PER_BEAT as u16).to_be_bytes());
+    for track in tracks {
+        out.extend_from_slice(b"MTrk");
+        out.extend_from_slice(&(track.len() as u32).to_be_bytes());
+        out.extend(track);
+    }
+    out
+}
+
+fn collect_events<const N: usize>(
+    bars: &[&Bar<N>],
+    starts: &[Rational],
+    voice: usize,
+    channel: u8,
+) -> Vec<Event> {
+    let mut events: Vec<Event> = bars
+        .iter()
+        .zip(starts)
+        .flat_map(|(bar, bar_start)| {
+            bar.notes[voice].iter().map(move |(offset, note)| Event {
+                start: *bar_start + *offset,
+                length: note.length,
+                channel: if note.drum.is_some() {
+                    DRUM_CHANNEL
+                } else {
+                    channel
+                },
+                pitch: note.midi(&bar.key).clamp(0.0, 127.0) as u8,
+                velocity: note.velocity(bar.dynamic, bar.energy) as u8,
+                tie: note.tie,
+            })
+        })
+        .collect();
+    events.sort_by_key(|x| x.start);
+    events
+}
+
+fn merge_ties(events: Vec<Event>) -> Vec<Event> {
+    let mut merged: Vec<Event> = Vec::new();
+    for event in events {
+        let open = merged.iter_mut().find(|x| {
+            x.tie
+                && x.pitch == event.pitch
+                && x.channel == event.channel
+                && x.start + x.length == event.start
+        });
+        match open {
+            Some(x) => {
+                x.length += event.length;
+                x.tie = event.tie;
+            }
+            None => merged.push(event),
+        }
+    }
+    merged
+}
+
+fn tempo_track<const N: usize>(bars: &[&Bar<N>], starts: &[Rational]) -> Vec<u8> {
+    let mut messages: Vec<(i64, Vec<u8>)> = Vec::new();
+    let mut previous: Option<&Bar<N>> = None;
+    for (bar, start) in bars.iter().zip(starts) {
+        let tick = ticks(*start);
+        if previous.is_none_or(|x| x.bpm != bar.bpm) {
+            let tempo = (60_000_000.0 / bar.bpm).round() as u32;
+            let mut message = vec![0xFF, 0x51, 0x03];
+            message.extend_from_slice(&tempo.to_be_bytes()[1..]);
+            messages.push((tick, message));
+        }
+        if previous.is_none_or(|x| x.beats != bar.beats) {
+            messages.push((tick, vec![0xFF, 0x58, 0x04, bar.beats, 0x02, 0x18, 0x08]));
+        }
+        previous = Some(bar);
+    }
+    encode_track(messages)
+}
+
+fn note_track(name: &str, events: &[Event]) -> Vec<u8> {
+    let mut name_message = vec![0xFF, 0x03];
+    write_variable(&mut name_message, name.len() as u32);
+    name_message.extend_from_slice(name.as_bytes());
+
+    let mut messages = vec![(0, name_message)];
+    for event in events {
+        let on = vec![0x90 | event.channel, event.pitch, event.velocity];
+        let off = vec![0x80 | event.channel, event.pitch, 0];
+        messages.push((ticks(event.start), on));
+        messages.push((ticks(event.start + event.length), off));
+    }
+    messages.sort_by_key(|(tick, message)| (*tick, message[0] & 0xF0 != 0x80));
+    encode_track(messages)
+}
+
+fn encode_track(messages: Vec<(i64, Vec<u8>)>) -> Vec<u8> {
+    let mut track = Vec::new();
+    let mut time = 0;
+    for (tick, message) in messages {
+        write_variable(&mut track, (tick - time).max(0) as u32);
+        track.extend(message);
+        time = time.max(tick);
+    }
+    track.extend_from_slice(&[0x00, 0xFF, 0x2F, 0x00]);
+    track
+}
+
+fn ticks(beats: Rational) -> i64 {
+    (beats * TICKS_PER_BEAT).as_f64().round() as i64
+}
+
+fn write_variable(out: &mut Vec<u8>, value: u32) {
+    let mut bytes = vec![(value & 0x7F) as u8];
+    let mut value = value >> 7;
+    while value > 0 {
+        bytes.push((value & 0x7F) as u8 | 0x80);
+        value >>= 7;
+    }
+    out.extend(bytes.into_iter().rev());
+}
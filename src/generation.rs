@@ -1,9 +1,56 @@
+pub mod arpeggio;
+pub mod bass;
+pub mod chart;
+pub mod contour;
+pub mod counter;
+pub mod counterpoint;
+pub mod drums;
+pub mod energy;
+pub mod evolve;
+pub mod form;
+pub mod harmony;
+pub mod markov;
+pub mod motif;
+pub mod orchestration;
+pub mod phrase;
+pub mod registry;
+pub mod rhythm;
+pub mod seed;
+pub mod voicing;
+
 use std::rc::Rc;
 
 use rand::{rngs::StdRng, Rng};
 
 use crate::score::*;
 
+use self::{
+    arpeggio::Arpeggio,
+    bass::BassStyle,
+    contour::Contour,
+    counter::CounterStyle,
+    counterpoint::RuleSet,
+    drums::Groove,
+    evolve::Evolution,
+    harmony::Harmony,
+    markov::MarkovModel,
+    motif::Motif,
+    orchestration::Orchestration,
+    phrase::Phrase,
+    registry::Registry,
+    rhythm::{MelodyRhythm, Rhythm},
+    seed::Seed,
+    voicing::{Voicing, VoicingStyle},
+};
+
+#[derive(Clone, Debug)]
+pub enum Melody {
+    Shapes,
+    Markov(Rc<MarkovModel>),
+    Contour(Contour),
+    Motif(Rc<Motif>),
+}
+
 #[derive(Clone, Debug)]
 pub struct SectionSettings {
     length: usize,
@@ -11,153 +58,363 @@ pub struct SectionSettings {
     bpm: f64,
     beats: u8,
     dynamic: Dynamic,
+    shuffle: bool,
+    melody: Melody,
+    range: (i32, i32),
+    bass: BassStyle,
+    groove: Groove,
+    percussion: Vec<(Drum, Rhythm)>,
+    arpeggio: Option<Arpeggio>,
+    voicing: Voicing,
+    counter: Option<CounterStyle>,
+    harmony: Harmony,
+    counterpoint: Option<RuleSet>,
+    progression: Option<Vec<Chord>>,
+    evolution: Option<Evolution>,
+    rhythm: MelodyRhythm,
+    orchestration: Orchestration,
+    energy: Vec<f64>,
 }
 
 impl SectionSettings {
-    pub fn new(length: usize, key: Rc<Key>, bpm: f64, beats: u8, dynamic: Dynamic) -> Self {
+    pub fn new(
+        length: usize,
+        key: Rc<Key>,
+        bpm: f64,
+        beats: u8,
+        dynamic: Dynamic,
+        shuffle: bool,
+    ) -> Self {
         Self {
             length,
             key,
             bpm,
             beats,
             dynamic,
+            shuffle,
+            melody: Melody::Shapes,
+            range: (35, 46),
+            bass: BassStyle::Roots,
+            groove: Groove::Rock,
+            percussion: Vec::new(),
+            arpeggio: None,
+            voicing: Voicing::new(VoicingStyle::Closed),
+            counter: None,
+            harmony: Harmony::default(),
+            counterpoint: None,
+            progression: None,
+            evolution: None,
+            rhythm: MelodyRhythm::default(),
+            orchestration: Orchestration::default(),
+            energy: Vec::new(),
         }
     }
-}
-
-pub fn generate_section<const N: usize>(rng: &mut StdRng, settings: SectionSettings) -> Section<N> {
-    let mut bars = vec![
-        Bar::new(
-            settings.beats,
-            settings.bpm,
-            settings.key.clone(),
-            settings.dynamic,
+    pub fn with_melody(mut self, melody: Melody) -> Self {
+        self.melody = melody;
+        self
+    }
+    pub fn with_bass(mut self, bass: BassStyle) -> Self {
+        self.bass = bass;
+        self
+    }
+    pub fn with_groove(mut self, groove: Groove) -> Self {
+        self.groove = groove;
+        self
+    }
+    pub fn with_percussion(mut self, percussion: &[(Drum, Rhythm)]) -> Self {
+        self.percussion = percussion.to_vec();
+        self
+    }
+    pub fn with_arpeggio(mut self, arpeggio: Arpeggio) -> Self {
+        self.arpeggio = Some(arpeggio);
+        self
+    }
+    pub fn with_voicing(mut self, voicing: Voicing) -> Self {
+        self.voicing = voicing;
+        self
+    }
+    pub fn with_counter(mut self, counter: CounterStyle) -> Self {
+        self.counter = Some(counter);
+        self
+    }
+    pub fn with_harmony(mut self, harmony: Harmony) -> Self {
+        self.harmony = harmony;
+        self
+    }
+    pub fn with_counterpoint(mut self, rules: RuleSet) -> Self {
+        self.counterpoint = Some(rules);
+        self
+    }
+    pub fn with_progression(mut self, progression: &[Chord]) -> Self {
+        self.progression = (!progression.is_empty()).then(|| progression.to_vec());
+        self
+    }
+    pub fn with_rhythm(mut self, rhythm: MelodyRhythm) -> Self {
+        self.rhythm = rhythm;
+        self
+    }
+    pub fn with_orchestration(mut self, orchestration: Orchestration) -> Self {
+        self.orchestration = orchestration;
+        self
+    }
+    pub fn with_energy(mut self, levels: &[f64]) -> Self {
+        self.energy = levels.to_vec();
+        self
+    }
+    pub fn with_evolution(mut self, evolution: Evolution) -> Self {
+        self.evolution = Some(evolution);
+        self
+    }
+    pub fn with_range(
+        mut self,
+        (low_octave, low): (u8, u8),
+        (high_octave, high): (u8, u8),
+    ) -> Self {
+        self.range = (
+            low_octave as i32 * 7 + low as i32,
+            high_octave as i32 * 7 + high as i32,
         );
-        settings.length
-    ];
-
-    generate_melody(rng, 0, &mut bars);
-    generate_chords(rng, 0, 1, &mut bars);
-    generate_beat(rng, 2, 3, &mut bars);
+        self
+    }
+    pub fn raised(mut self, steps: i32) -> Self {
+        self.range = (self.range.0 + steps, self.range.1 + steps);
+        self
+    }
+    pub fn length(&self) -> usize {
+        self.length
+    }
+    pub fn key(&self) -> &Key {
+        &self.key
+    }
+    pub fn beats(&self) -> u8 {
+        self.beats
+    }
+    pub fn dynamic(&self) -> Dynamic {
+        self.dynamic
+    }
+    pub fn shuffle(&self) -> bool {
+        self.shuffle
+    }
+    pub fn range(&self) -> (i32, i32) {
+        self.range
+    }
+}
 
-    Section::from_bars(bars)
+pub fn generate_section<const N: usize>(
+    seed: Seed,
+    settings: SectionSettings,
+) -> Result<Section<N>, anyhow::Error> {
+    Registry::default().generate(seed, &settings)
 }
 
-pub fn generate_beat<const N: usize>(
+pub fn generate_melody<const N: usize>(
     rng: &mut StdRng,
-    bassdrum_voice: usize,
-    snare_voice: usize,
-    bars: &mut Vec<Bar<N>>,
+    rhythm: &MelodyRhythm,
+    phrases: &[Phrase],
+    voice: usize,
+    shuffle: bool,
+    bars: &mut [Bar<N>],
 ) {
-    let pattern = rng.gen_range(0..=0);
+    let length = bars.first().map_or(Rational::ZERO, |x| x.length());
+    let patterns: Vec<Vec<(Rational, Rational)>> =
+        (0..5).map(|_| rhythm.pattern(rng, length, false)).collect();
 
-    for i in 0..bars.len() {
-        let bar = bars.get_mut(i).unwrap();
-
-        if pattern == 0 {
-            bar.add_note(bassdrum_voice, 0.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 1.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 1.5, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 2.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 3.0, Note::new(1.0, 0, 3, None));
-        } else if pattern == 1 {
-            bar.add_note(bassdrum_voice, 0.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 0.70, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 1.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 1.5, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 2.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 2.70, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 3.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 3.5, Note::new(1.0, 0, 3, None));
-        } else if pattern == 2 {
-            bar.add_note(bassdrum_voice, 0.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 1.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 2.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 3.0, Note::new(1.0, 0, 3, None));
-        } else {
-            bar.add_note(bassdrum_voice, 0.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 0.5, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 1.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 1.5, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 2.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 2.5, Note::new(1.0, 0, 3, None));
-            bar.add_note(bassdrum_voice, 3.0, Note::new(1.0, 0, 3, None));
-            bar.add_note(snare_voice, 3.5, Note::new(1.0, 0, 3, None));
+    for (i, bar) in bars.iter_mut().enumerate() {
+        let pattern = match phrases.iter().any(|x| x.end() == i + 1) {
+            true => rhythm.pattern(rng, bar.length(), true),
+            false => patterns[rng.gen_range(0..patterns.len())].clone(),
+        };
+        for (start, duration) in pattern {
+            if start >= bar.length() {
+                continue;
+            }
+            let pitch = melody_pitch(rng, bar.chord.as_ref(), start.fract() == Rational::ZERO);
+            let duration = duration.min(bar.length() - start);
+
+            if duration == Rational::ONE && (shuffle || rng.gen_bool(0.15)) {
+                let tuplet = Tuplet::triplet(start, duration);
+                let figure: &[(u8, i64)] = if shuffle {
+                    &[(0, 2), (2, 1)]
+                } else {
+                    &[(0, 1), (1, 1), (2, 1)]
+                };
+                for (k, &(index, units)) in figure.iter().enumerate() {
+                    let pitch = if k == 0 {
+                        pitch
+                    } else {
+                        melody_pitch(rng, bar.chord.as_ref(), false)
+                    };
+                    let offset = tuplet.offset(index);
+                    let length = tuplet.unit() * units;
+                    bar.add_note(
+                        voice,
+                        offset,
+                        Note::new(length, pitch, 5, bar.accidental(pitch)),
+                    );
+                }
+                bar.add_tuplet(voice, tuplet);
+                continue;
+            }
+
+            bar.add_note(
+                voice,
+                start,
+                Note::new(duration, pitch, 5, bar.accidental(pitch)),
+            );
         }
     }
+
+    tie_over_bar_lines(rng, voice, bars);
 }
 
-pub fn generate_melody<const N: usize>(rng: &mut StdRng, voice: usize, bars: &mut Vec<Bar<N>>) {
-    let shapes: Vec<Vec<u8>> = vec![
-        vec![6, 0, 0, 0, 0, 0, 2, 0],
-        vec![2, 0, 6, 0, 0, 0, 0, 0],
-        vec![6, 0, 0, 0, 0, 0, 0, 0],
-        vec![8, 0, 0, 0, 0, 0, 0, 0],
-        vec![4, 0, 0, 0, 4, 0, 0, 0],
-        vec![2, 0, 2, 0, 4, 0, 0, 0],
-        vec![4, 0, 0, 0, 2, 0, 0, 0],
-        vec![2, 0, 2, 0, 2, 0, 0, 0],
-        vec![4, 0, 0, 0, 2, 0, 2, 0],
-        vec![2, 0, 2, 0, 2, 0, 2, 0],
-    ];
-
-    let selected_shapes: Vec<Vec<u8>> = (0..5)
-        .map(|_| shapes[rng.gen_range(0..shapes.len())].clone())
-        .collect();
+fn nearest_degree(position: i32, degree: i32) -> i32 {
+    let below = position - (position - degree).rem_euclid(7);
+    if position - below > 3 {
+        below + 7
+    } else {
+        below
+    }
+}
 
+fn melody_pitch(rng: &mut StdRng, chord: Option<&Chord>, strong: bool) -> u8 {
+    match chord {
+        Some(chord) if strong && rng.gen_bool(0.85) => {
+            let tones = chord.tones();
+            tones[rng.gen_range(0..tones.len())]
+        }
+        _ => rng.gen_range(0..7),
+    }
+}
+
+fn tie_over_bar_lines<const N: usize>(rng: &mut StdRng, voice: usize, bars: &mut [Bar<N>]) {
+    for i in 1..bars.len() {
+        let (previous, current) = bars.split_at_mut(i);
+        let previous = previous.last_mut().unwrap();
+        let bar_length = previous.length();
+        let mut held: Vec<&mut Note> = previous.notes[voice]
+            .iter_mut()
+            .filter(|(offset, x)| *offset + x.length == bar_length)
+            .map(|x| &mut x.1)
+            .collect();
+        if held.is_empty() || !rng.gen_bool(0.2) {
+            continue;
+        }
+
+        let (pitch, octave, accidental) = (held[0].pitch, held[0].octave, held[0].accidental);
+        let onset = current[0].notes[voice]
+            .iter()
+            .any(|x| x.0 == Rational::ZERO);
+        if !onset
+            || current[0]
+                .chord
+                .as_ref()
+                .is_some_and(|x| !x.contains(pitch) || x.accidental(pitch) != accidental)
+        {
+            continue;
+        }
+        held.iter_mut().for_each(|x| x.tie = true);
+        current[0].notes[voice]
+            .iter_mut()
+            .filter(|(offset, _)| *offset == Rational::ZERO)
+            .for_each(|x| {
+                x.1.pitch = pitch;
+                x.1.octave = octave;
+                x.1.accidental = accidental;
+            });
+    }
+}
+
+fn fix_ties<const N: usize>(voice: usize, bars: &mut [Bar<N>]) {
     for i in 0..bars.len() {
-        let shape = selected_shapes[rng.gen_range(0..selected_shapes.len())].as_slice();
-        let bar = bars.get_mut(i).unwrap();
-        for j in 0..8 {
-            let pitch = rng.gen_range(0..7);
-            if shape[j] == 0 {
-                continue;
+        let length = bars[i].length();
+        let next: Vec<(u8, u8, Option<bool>)> = bars
+            .get(i + 1)
+            .map(|bar| {
+                bar.notes[voice]
+                    .iter()
+                    .filter(|x| x.0 == Rational::ZERO)
+                    .map(|x| (x.1.pitch, x.1.octave, x.1.accidental))
+                    .collect()
+            })
+            .unwrap_or_default();
+        for (offset, note) in bars[i].notes[voice].iter_mut() {
+            let held = *offset + note.length == length;
+            if note.tie && !(held && next.contains(&(note.pitch, note.octave, note.accidental))) {
+                note.tie = false;
             }
-            let duration = shape[j] as f64 * 0.5;
-            bar.add_note(voice, j as f64 * 0.5, Note::new(duration, pitch, 5, None));
-            bar.add_note(voice, j as f64 * 0.5, Note::new(duration, pitch, 4, None));
         }
     }
 }
 
 pub fn generate_chords<const N: usize>(
     rng: &mut StdRng,
-    melody: usize,
+    voicing: &Voicing,
     voice: usize,
-    bars: &mut Vec<Bar<N>>,
+    bars: &mut [Bar<N>],
 ) {
-    for i in 0..bars.len() {
-        let bar = bars.get_mut(i).unwrap();
-        let melody_notes: Vec<Note> = bar.notes[melody].iter().map(|x| x.1.clone()).collect();
-        let p1 = melody_notes
-            .get(0)
-            .cloned()
-            .unwrap_or(Note::new(4.0, 0, 5, None));
-
-        bar.add_note(
-            voice,
-            0.0,
-            Note::new(bar.beats as f64, p1.pitch, 4, p1.accidental),
-        );
-        bar.add_note(
-            voice,
-            0.0,
-            Note::new(bar.beats as f64, p1.pitch + 2, 4, p1.accidental),
-        );
-        bar.add_note(
-            voice,
-            0.0,
-            Note::new(bar.beats as f64, p1.pitch + 4, 4, p1.accidental),
-        );
-        bar.add_note(voice, 1.0, Note::new(3.0, p1.pitch, 4, p1.accidental));
-        bar.add_note(voice, 2.0, Note::new(2.0, p1.pitch + 2, 4, p1.accidental));
-        bar.add_note(voice, 3.0, Note::new(1.0, p1.pitch + 4, 4, p1.accidental));
-        if rng.gen_bool(0.5) {
+    let chords: Vec<Chord> = bars
+        .iter()
+        .map(|bar| bar.chord.clone().unwrap_or(Chord::new(0, false)))
+        .collect();
+    let voicings = voicing.voice(&chords);
+
+    let push = Rational::new(1, 2);
+    let anticipations: Vec<bool> = (0..bars.len())
+        .map(|i| i + 1 < bars.len() && rng.gen_bool(0.25))
+        .collect();
+    let block_ends: Vec<Rational> = bars
+        .iter()
+        .zip(anticipations.iter())
+        .map(|(bar, &anticipate)| bar.length() - if anticipate { push } else { Rational::ZERO })
+        .collect();
+
+    for (i, bar) in bars.iter_mut().enumerate() {
+        if i == 0 || !anticipations[i - 1] {
+            add_block_chord(
+                bar,
+                voice,
+                Rational::ZERO,
+                block_ends[i],
+                &chords[i],
+                &voicings[i],
+            );
+        }
+        let staggered = bar.beats.saturating_sub(1).min(3) as usize;
+        for (beat, &position) in voicings[i].iter().skip(1).take(staggered).enumerate() {
+            let beat = Rational::int(beat as i64 + 1);
             bar.add_note(
                 voice,
-                0.0,
-                Note::new(bar.beats as f64, p1.pitch + 6, 4, p1.accidental),
+                beat,
+                position_note(&chords[i], bar.length() - beat, position),
             );
         }
+        if anticipations[i] {
+            let start = bar.length() - push;
+            let length = push + block_ends[i + 1];
+            add_block_chord(bar, voice, start, length, &chords[i + 1], &voicings[i + 1]);
+        }
     }
 }
+
+fn add_block_chord<const N: usize>(
+    bar: &mut Bar<N>,
+    voice: usize,
+    start: Rational,
+    length: Rational,
+    chord: &Chord,
+    voicing: &[i32],
+) {
+    for &position in voicing {
+        bar.add_note(voice, start, position_note(chord, length, position));
+    }
+}
+
+fn position_note(chord: &Chord, length: Rational, position: i32) -> Note {
+    let pitch = position.rem_euclid(7) as u8;
+    Note::new(
+        length,
+        pitch,
+        position.div_euclid(7) as u8,
+        chord.accidental(pitch),
+    )
+}
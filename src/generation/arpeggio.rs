@@ -0,0 +1,163 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+use super::rhythm::Rhythm;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ArpPattern {
+    Up,
+    Down,
+    UpDown,
+    Random,
+    Pattern(Vec<u8>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rate {
+    Eighths,
+    Sixteenths,
+    Triplets,
+}
+
+impl Rate {
+    fn rhythm(&self) -> Rhythm {
+        let count = match self {
+            Self::Eighths => 2,
+            Self::Sixteenths => 4,
+            Self::Triplets => 3,
+        };
+        Rhythm::Polyrhythm { count, span: 1 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Arpeggio {
+    pattern: ArpPattern,
+    rhythm: Rhythm,
+    octaves: u8,
+    gate: Rational,
+    accents: Vec<bool>,
+}
+
+impl Arpeggio {
+    pub fn new(pattern: ArpPattern, rate: Rate) -> Self {
+        Self {
+            pattern,
+            rhythm: rate.rhythm(),
+            octaves: 1,
+            gate: Rational::ONE,
+            accents: vec![true, false, false, false],
+        }
+    }
+    pub fn spanning(mut self, octaves: u8) -> Self {
+        self.octaves = octaves.max(1);
+        self
+    }
+    pub fn gated(mut self, gate: Rational) -> Self {
+        self.gate = gate;
+        self
+    }
+    pub fn accented(mut self, accents: &[bool]) -> Self {
+        self.accents = accents.to_vec();
+        self
+    }
+    fn index(&self, rng: &mut StdRng, step: usize, count: usize) -> usize {
+        match &self.pattern {
+            ArpPattern::Up => step % count,
+            ArpPattern::Down => count - 1 - step % count,
+            ArpPattern::UpDown if count > 1 => {
+                let cycle = step % (2 * count - 2);
+                if cycle < count {
+                    cycle
+                } else {
+                    2 * count - 2 - cycle
+                }
+            }
+            ArpPattern::UpDown => 0,
+            ArpPattern::Random => rng.gen_range(0..count),
+            ArpPattern::Pattern(pattern) if !pattern.is_empty() => {
+                pattern[step % pattern.len()] as usize % count
+            }
+            ArpPattern::Pattern(_) => step % count,
+        }
+    }
+}
+
+pub fn generate_arpeggio<const N: usize>(
+    rng: &mut StdRng,
+    arpeggio: &Arpeggio,
+    harmony: usize,
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    for i in 0..bars.len() {
+        let (onsets, tuplets) = arpeggio.rhythm.onsets(bars[i].length());
+        let notes: Vec<(Rational, Note)> = onsets
+            .into_iter()
+            .enumerate()
+            .filter_map(|(step, (offset, length))| {
+                let tones = arpeggio_tones(arpeggio, bars, harmony, i, offset);
+                if tones.is_empty() {
+                    return None;
+                }
+                let mut note = tones[arpeggio.index(rng, step, tones.len())].clone();
+                note.length = length * arpeggio.gate;
+                note.tie = false;
+                note.accent = arpeggio
+                    .accents
+                    .get(step % arpeggio.accents.len().max(1))
+                    .copied()
+                    .unwrap_or(false);
+                Some((offset, note))
+            })
+            .collect();
+
+        let bar = &mut bars[i];
+        for (offset, note) in notes {
+            bar.add_note(voice, offset, note);
+        }
+        for tuplet in tuplets {
+            bar.add_tuplet(voice, tuplet);
+        }
+    }
+}
+
+fn arpeggio_tones<const N: usize>(
+    arpeggio: &Arpeggio,
+    bars: &[Bar<N>],
+    harmony: usize,
+    bar: usize,
+    offset: Rational,
+) -> Vec<Note> {
+    let held = bar
+        .checked_sub(1)
+        .map(|x| &bars[x])
+        .into_iter()
+        .flat_map(|x| {
+            x.notes[harmony]
+                .iter()
+                .filter(|x| !x.1.doubling)
+                .map(move |(start, note)| (*start - x.length(), note))
+        });
+    let sounding = bars[bar].notes[harmony]
+        .iter()
+        .filter(|x| !x.1.doubling)
+        .map(|(start, note)| (*start, note))
+        .chain(held)
+        .filter(|(start, note)| *start <= offset && offset < *start + note.length);
+
+    let key = &bars[bar].key;
+    let mut tones: Vec<Note> = sounding.map(|x| x.1.clone()).collect();
+    tones.sort_by(|a, b| a.midi(key).total_cmp(&b.midi(key)));
+    tones.dedup_by(|a, b| a.midi(key) == b.midi(key));
+
+    let base = tones.clone();
+    for octave in 1..arpeggio.octaves {
+        tones.extend(base.iter().map(|x| Note {
+            octave: x.octave + octave,
+            ..x.clone()
+        }));
+    }
+    tones
+}
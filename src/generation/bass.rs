@@ -0,0 +1,178 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+const OCTAVE: i32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BassStyle {
+    Roots,
+    RootFifth,
+    Octaves,
+    Kick,
+    Walking,
+}
+
+pub fn generate_bass<const N: usize>(
+    rng: &mut StdRng,
+    style: BassStyle,
+    voice: usize,
+    drums: usize,
+    bars: &mut [Bar<N>],
+) {
+    let roots: Vec<(i32, Option<bool>)> = bars
+        .iter()
+        .map(|bar| {
+            let (degree, accidental) = bar.chord.as_ref().map_or((0, None), |x| x.bass());
+            (root_position(degree), accidental)
+        })
+        .collect();
+
+    for (i, bar) in bars.iter_mut().enumerate() {
+        let root = roots[i].0;
+        let next = roots.get(i + 1).map(|x| x.0);
+        let length = bar.length();
+        let notes: Vec<(Rational, Rational, i32)> = match style {
+            BassStyle::Roots => (0..bar.beats as i64)
+                .step_by(2)
+                .map(|x| (Rational::int(x), Rational::int(2), root))
+                .collect(),
+            BassStyle::RootFifth => (0..bar.beats as i64)
+                .step_by(2)
+                .map(|x| {
+                    let fifth = if x % 4 == 0 { 0 } else { 4 };
+                    (Rational::int(x), Rational::int(2), root + fifth)
+                })
+                .collect(),
+            BassStyle::Octaves => (0..bar.beats as i64 * 2)
+                .map(|x| {
+                    let octave = if x % 2 == 0 { 0 } else { 7 };
+                    (Rational::new(x, 2), Rational::new(1, 2), root + octave)
+                })
+                .collect(),
+            BassStyle::Kick => kick_line(rng, bar, drums, root),
+            BassStyle::Walking => walking_line(rng, bar, root, next),
+        };
+
+        for (start, note_length, position) in notes {
+            let note_length = note_length.min(length - start);
+            let pitch = position.rem_euclid(7) as u8;
+            let accidental = match position == root {
+                true => roots[i].1,
+                false => bar.accidental(pitch),
+            };
+            let note = Note::new(note_length, pitch, position.div_euclid(7) as u8, accidental);
+            bar.add_note(voice, start, note);
+        }
+
+        if style == BassStyle::Walking {
+            if let Some(next) = roots.get(i + 1).copied().filter(|_| rng.gen_bool(0.4)) {
+                approach_chromatically(bar, voice, next);
+            }
+        }
+    }
+}
+
+fn root_position(degree: u8) -> i32 {
+    let position = OCTAVE * 7 + degree as i32;
+    if degree >= 5 {
+        position - 7
+    } else {
+        position
+    }
+}
+
+fn kick_line<const N: usize>(
+    rng: &mut StdRng,
+    bar: &Bar<N>,
+    drums: usize,
+    root: i32,
+) -> Vec<(Rational, Rational, i32)> {
+    let mut kicks: Vec<Rational> = bar.notes[drums]
+        .iter()
+        .filter(|x| x.1.drum == Some(Drum::Kick))
+        .map(|x| x.0)
+        .collect();
+    kicks.sort();
+    kicks.dedup();
+    if kicks.is_empty() {
+        kicks.push(Rational::ZERO);
+    }
+
+    let mut ends = kicks.clone();
+    ends.remove(0);
+    ends.push(bar.length());
+    kicks
+        .into_iter()
+        .zip(ends)
+        .enumerate()
+        .map(|(i, (start, end))| {
+            let length = (end - start).min(Rational::ONE);
+            let interval = match i {
+                0 => 0,
+                _ if rng.gen_bool(0.25) => 4,
+                _ if rng.gen_bool(0.15) => 7,
+                _ => 0,
+            };
+            (start, length, root + interval)
+        })
+        .collect()
+}
+
+fn walking_line<const N: usize>(
+    rng: &mut StdRng,
+    bar: &Bar<N>,
+    root: i32,
+    next: Option<i32>,
+) -> Vec<(Rational, Rational, i32)> {
+    let beats = bar.beats as i32;
+    let target = next.unwrap_or(root);
+    let mut line = vec![root];
+    for beat in 1..beats {
+        let previous = *line.last().unwrap();
+        let remaining = beats - beat;
+        let position = if remaining == 1 {
+            if rng.gen_bool(0.5) {
+                target - 1
+            } else {
+                target + 1
+            }
+        } else if rng.gen_bool(0.6) {
+            let tones = [root + 2, root + 4, root + 7];
+            tones[rng.gen_range(0..tones.len())]
+        } else {
+            let direction = match (target - previous).signum() {
+                0 if rng.gen_bool(0.5) => 1,
+                0 => -1,
+                x => x,
+            };
+            previous + direction
+        };
+        line.push(position);
+    }
+    line.into_iter()
+        .enumerate()
+        .map(|(i, x)| (Rational::int(i as i64), Rational::ONE, x))
+        .collect()
+}
+
+fn approach_chromatically<const N: usize>(
+    bar: &mut Bar<N>,
+    voice: usize,
+    (next, accidental): (i32, Option<bool>),
+) {
+    let key = bar.key.clone();
+    let last = bar.length() - Rational::ONE;
+    let target = Note::new(
+        Rational::ONE,
+        next.rem_euclid(7) as u8,
+        next.div_euclid(7) as u8,
+        accidental,
+    );
+    let approach = key.note(target.midi(&key) as u8 - 1, Rational::ONE);
+    for (offset, note) in bar.notes[voice].iter_mut() {
+        if *offset == last {
+            *note = approach.clone();
+        }
+    }
+}
@@ -0,0 +1,304 @@
+use std::fmt;
+
+use anyhow::anyhow;
+
+use crate::score::{Chord, Key, Mode};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quality {
+    Major,
+    Minor,
+    Diminished,
+    Augmented,
+}
+
+impl Quality {
+    fn intervals(&self) -> [i8; 3] {
+        match self {
+            Self::Major => [0, 4, 7],
+            Self::Minor => [0, 3, 7],
+            Self::Diminished => [0, 3, 6],
+            Self::Augmented => [0, 4, 8],
+        }
+    }
+}
+
+const SUFFIXES: [(&str, Quality, Option<i8>); 22] = [
+    ("", Quality::Major, None),
+    ("maj7", Quality::Major, Some(11)),
+    ("M7", Quality::Major, Some(11)),
+    ("7", Quality::Major, Some(10)),
+    ("9", Quality::Major, Some(10)),
+    ("6", Quality::Major, None),
+    ("m", Quality::Minor, None),
+    ("min", Quality::Minor, None),
+    ("-", Quality::Minor, None),
+    ("m7", Quality::Minor, Some(10)),
+    ("min7", Quality::Minor, Some(10)),
+    ("-7", Quality::Minor, Some(10)),
+    ("m9", Quality::Minor, Some(10)),
+    ("m6", Quality::Minor, None),
+    ("dim", Quality::Diminished, None),
+    ("o", Quality::Diminished, None),
+    ("dim7", Quality::Diminished, Some(9)),
+    ("o7", Quality::Diminished, Some(9)),
+    ("m7b5", Quality::Diminished, Some(10)),
+    ("ø", Quality::Diminished, Some(10)),
+    ("aug", Quality::Augmented, None),
+    ("+", Quality::Augmented, None),
+];
+
+fn parse_note(name: &str) -> Option<(i8, i8, &str)> {
+    let mut chars = name.chars();
+    let root = match chars.next()? {
+        'C' => 0,
+        'D' => 2,
+        'E' => 4,
+        'F' => 5,
+        'G' => 7,
+        'A' => 9,
+        'B' => 11,
+        _ => return None,
+    };
+    let rest = chars.as_str();
+    if let Some(rest) = rest.strip_prefix('#') {
+        Some(((root + 1) % 12, 1, rest))
+    } else if let Some(rest) = rest.strip_prefix('b') {
+        Some(((root + 11) % 12, -1, rest))
+    } else {
+        Some((root, 0, rest))
+    }
+}
+
+fn alteration(target: i8, natural: i8) -> Option<Option<bool>> {
+    match (target - natural).rem_euclid(12) {
+        0 => Some(None),
+        1 => Some(Some(true)),
+        11 => Some(Some(false)),
+        _ => None,
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ChordSymbol {
+    name: String,
+    pub root: i8,
+    pub quality: Quality,
+    pub seventh: Option<i8>,
+    pub bass: Option<i8>,
+    sign: i8,
+    dominant: bool,
+}
+
+impl ChordSymbol {
+    pub fn parse(symbol: &str) -> Result<Self, anyhow::Error> {
+        let invalid = || anyhow!("unknown chord symbol {}", symbol);
+        let (chord, bass) = match symbol.split_once('/') {
+            Some((chord, bass)) => match parse_note(bass) {
+                Some((bass, _, "")) => (chord, Some(bass)),
+                _ => return Err(invalid()),
+            },
+            None => (symbol, None),
+        };
+        let (root, sign, suffix) = parse_note(chord).ok_or_else(invalid)?;
+        let (_, quality, seventh) = SUFFIXES
+            .iter()
+            .find(|x| x.0 == suffix)
+            .ok_or_else(invalid)?;
+        Ok(Self {
+            name: symbol.to_string(),
+            root,
+            quality: *quality,
+            seventh: *seventh,
+            bass,
+            sign,
+            dominant: matches!(suffix, "7" | "9"),
+        })
+    }
+    fn intervals(&self) -> Vec<i8> {
+        let mut intervals = self.quality.intervals().to_vec();
+        intervals.extend(self.seventh);
+        intervals
+    }
+    fn tones(&self) -> [i8; 3] {
+        self.quality
+            .intervals()
+            .map(|x| (self.root + x).rem_euclid(12))
+    }
+    fn fit(&self, key: &Key) -> (Chord, bool) {
+        let tones = self.tones();
+        let triad = |degree: u8| [0, 2, 4].map(|x| key.pitch_class(degree + x));
+        let score = |degree: u8| {
+            let shared = triad(degree).iter().filter(|x| tones.contains(x)).count();
+            shared * 2 + tones.contains(&key.pitch_class(degree)) as usize
+        };
+        let degree = (0..7).rev().max_by_key(|&x| score(x)).unwrap_or(0);
+        let exact = triad(degree) == tones;
+        (Chord::new(degree, self.seventh.is_some()), exact)
+    }
+    fn spell(&self, key: &Key) -> Option<Chord> {
+        let mismatch = |accidental: Option<bool>| match accidental {
+            Some(sharp) => sharp != (self.sign > 0),
+            None => self.sign != 0,
+        };
+        let (_, degree, accidentals) = (0..7)
+            .filter_map(|degree: u8| {
+                let mut accidentals = [None; 4];
+                for (i, interval) in self.intervals().into_iter().enumerate() {
+                    let natural = key.pitch_class(degree + 2 * i as u8);
+                    accidentals[i] = alteration((self.root + interval).rem_euclid(12), natural)?;
+                }
+                let count = accidentals.iter().filter(|x| x.is_some()).count();
+                Some(((count, mismatch(accidentals[0])), degree, accidentals))
+            })
+            .min_by_key(|x| x.0)?;
+        let chord = Chord::new(degree, self.seventh.is_some()).altered(accidentals);
+        let Some(bass) = self.bass else {
+            return Some(chord);
+        };
+        let (_, degree, accidental) = (0..7)
+            .filter_map(|degree: u8| {
+                let accidental = alteration(bass, key.pitch_class(degree))?;
+                let tone = chord.contains(degree) && chord.accidental(degree) == accidental;
+                Some(((!tone, accidental.is_some()), degree, accidental))
+            })
+            .min_by_key(|x| x.0)?;
+        Some(chord.over(degree, accidental))
+    }
+}
+
+impl fmt::Display for ChordSymbol {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "{}", self.name)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chart {
+    symbols: Vec<ChordSymbol>,
+}
+
+impl Chart {
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut symbols: Vec<ChordSymbol> = Vec::new();
+        for bar in text.split('|') {
+            let chords: Vec<&str> = bar.split_whitespace().collect();
+            match chords[..] {
+                [] => continue,
+                ["%"] => match symbols.last() {
+                    Some(previous) => symbols.push(previous.clone()),
+                    None => return Err(anyhow!("chord chart starts with a repeat")),
+                },
+                [symbol] => symbols.push(ChordSymbol::parse(symbol)?),
+                _ => {
+                    return Err(anyhow!(
+                        "chord chart needs one chord per bar: {}",
+                        bar.trim()
+                    ))
+                }
+            }
+        }
+        if symbols.is_empty() {
+            return Err(anyhow!("empty chord chart {}", text));
+        }
+        Ok(Self { symbols })
+    }
+    pub fn symbols(&self) -> &[ChordSymbol] {
+        &self.symbols
+    }
+    pub fn progression(&self, key: &Key) -> Vec<Chord> {
+        self.symbols
+            .iter()
+            .map(|x| x.spell(key).unwrap_or_else(|| x.fit(key).0))
+            .collect()
+    }
+    pub fn misfits(&self, key: &Key) -> Vec<&ChordSymbol> {
+        self.symbols
+            .iter()
+            .filter(|x| x.spell(key).is_none())
+            .collect()
+    }
+}
+
+impl fmt::Display for Chart {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "|")?;
+        for symbol in self.symbols.iter() {
+            write!(f, " {} |", symbol)?;
+        }
+        Ok(())
+    }
+}
+
+pub fn fit_key(charts: &[&Chart], modes: &[Mode]) -> Key {
+    let modes = modes
+        .iter()
+        .chain(Mode::ALL.iter().filter(|x| !modes.contains(x)));
+    let mut best: Option<((usize, usize), Key)> = None;
+    for &mode in modes {
+        for tonic in -4..=7 {
+            let key = Key::modal(tonic, mode);
+            let tonic = |symbol: &ChordSymbol| {
+                !symbol.dominant
+                    && symbol.fit(&key) == (Chord::new(0, symbol.seventh.is_some()), true)
+            };
+            let fits = charts
+                .iter()
+                .flat_map(|x| x.symbols.iter())
+                .filter(|x| x.fit(&key).1)
+                .count();
+            let home: usize = charts
+                .iter()
+                .map(|x| {
+                    x.symbols.iter().any(tonic) as usize
+                        + tonic(&x.symbols[0]) as usize
+                        + tonic(x.symbols.last().unwrap()) as usize
+                })
+                .sum();
+            if best.as_ref().is_none_or(|x| (fits, home) > x.0) {
+                best = Some(((fits, home), key));
+            }
+        }
+    }
+    best.map(|x| x.1).unwrap_or(Key::new(0, true))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn repeats_previous_bar() {
+        let chart = Chart::parse("| Am | % | F | % |").unwrap();
+        let names: Vec<String> = chart.symbols().iter().map(|x| x.to_string()).collect();
+        assert_eq!(names, ["Am", "Am", "F", "F"]);
+        assert!(Chart::parse("| % | C |").is_err());
+    }
+
+    #[test]
+    fn parses_flat_and_sharp_roots() {
+        let chart = Chart::parse("| Bb7 | F#m | C/E |").unwrap();
+        let symbols = chart.symbols();
+        assert_eq!((symbols[0].root, symbols[0].seventh), (10, Some(10)));
+        assert_eq!((symbols[1].root, symbols[1].quality), (6, Quality::Minor));
+        assert_eq!((symbols[2].root, symbols[2].bass), (0, Some(4)));
+    }
+
+    #[test]
+    fn rejects_invalid_symbols() {
+        for chart in ["| H |", "| Cx |", "| C/X |", "| C G |", "| |"] {
+            assert!(Chart::parse(chart).is_err(), "{}", chart);
+        }
+    }
+
+    #[test]
+    fn spells_chords_outside_the_key() {
+        let key = Key::new(0, true);
+        let chart = Chart::parse("| C | Bb | F/A |").unwrap();
+        let progression = chart.progression(&key);
+        assert_eq!(progression[1].degree, 6);
+        assert_eq!(progression[1].accidental(6), Some(false));
+        assert_eq!(progression[2].bass(), (5, None));
+        assert!(chart.misfits(&key).is_empty());
+    }
+}
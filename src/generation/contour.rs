@@ -0,0 +1,188 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+use super::{
+    harmony::weighted,
+    phrase::{Cadence, Phrase},
+    rhythm::MelodyRhythm,
+};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Contour {
+    Arch,
+    Rising,
+    Falling,
+    Wave,
+}
+
+impl Contour {
+    fn height(&self, progress: f64) -> f64 {
+        match self {
+            Self::Arch => (progress * std::f64::consts::PI).sin(),
+            Self::Rising => progress,
+            Self::Falling => 1.0 - progress,
+            Self::Wave => 0.5 - 0.4 * (progress * 4.0 * std::f64::consts::PI).cos(),
+        }
+    }
+    fn climax(&self, progress: f64) -> f64 {
+        match self {
+            Self::Arch => 1.0 - (progress - 0.6).abs(),
+            Self::Rising => progress,
+            Self::Falling => 1.0 - progress,
+            Self::Wave => 1.0 - (progress - 0.75).abs(),
+        }
+    }
+}
+
+struct Slot {
+    bar: usize,
+    start: Rational,
+    length: Rational,
+    strong: bool,
+}
+
+pub fn generate_contour_melody<const N: usize>(
+    rng: &mut StdRng,
+    contour: Contour,
+    rhythm: &MelodyRhythm,
+    range: (i32, i32),
+    phrases: &[Phrase],
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    let length = bars.first().map_or(Rational::ZERO, |x| x.length());
+    let patterns: Vec<Vec<(Rational, Rational)>> =
+        (0..5).map(|_| rhythm.pattern(rng, length, false)).collect();
+    let (low, high) = range;
+    let mut position = (low + high) / 2;
+    let mut interval = 0;
+
+    for phrase in phrases {
+        let length = bars[phrase.start].length();
+        let slots = phrase_slots(rng, rhythm, &patterns, phrase.start..phrase.end(), length);
+        if slots.is_empty() {
+            continue;
+        }
+
+        let climax = choose_climax(rng, contour, &slots);
+        let climax_position = high - rng.gen_range(0..=1).min(high - low);
+        for (i, slot) in slots.iter().enumerate() {
+            let chord = bars[slot.bar].chord.clone();
+            let next = if i == climax {
+                climax_position
+            } else if i + 1 == slots.len() {
+                cadence_position(position, range, phrase.cadence)
+            } else {
+                let progress = i as f64 / (slots.len() - 1).max(1) as f64;
+                let target = low as f64 + contour.height(progress) * (climax_position - low) as f64;
+                let ceiling = if i < climax {
+                    climax_position - 1
+                } else {
+                    climax_position - 2
+                };
+                melody_step(
+                    rng,
+                    (position, interval),
+                    (low, ceiling.max(low)),
+                    target,
+                    slot.strong.then_some(chord).flatten().as_ref(),
+                )
+            };
+            interval = next - position;
+            position = next;
+
+            let degree = position.rem_euclid(7) as u8;
+            let octave = position.div_euclid(7) as u8;
+            let accidental = bars[slot.bar].accidental(degree);
+            bars[slot.bar].add_note(
+                voice,
+                slot.start,
+                Note::new(slot.length, degree, octave, accidental),
+            );
+        }
+    }
+}
+
+fn phrase_slots(
+    rng: &mut StdRng,
+    rhythm: &MelodyRhythm,
+    patterns: &[Vec<(Rational, Rational)>],
+    bars: std::ops::Range<usize>,
+    bar_length: Rational,
+) -> Vec<Slot> {
+    let mut slots = Vec::new();
+    let last = bars.end.saturating_sub(1);
+    for bar in bars {
+        let pattern = match bar == last {
+            true => rhythm.pattern(rng, bar_length, true),
+            false => patterns[rng.gen_range(0..patterns.len())].clone(),
+        };
+        for (start, length) in pattern {
+            if start >= bar_length {
+                continue;
+            }
+            slots.push(Slot {
+                bar,
+                start,
+                length: length.min(bar_length - start),
+                strong: start.fract() == Rational::ZERO && start.floor() % 2 == 0,
+            });
+        }
+    }
+    slots
+}
+
+fn choose_climax(rng: &mut StdRng, contour: Contour, slots: &[Slot]) -> usize {
+    let candidates = slots.len().saturating_sub(1).max(1);
+    weighted(
+        rng,
+        (0..candidates).map(|i| {
+            let progress = i as f64 / (candidates - 1).max(1) as f64;
+            let strong = if slots[i].strong { 3.0 } else { 1.0 };
+            (
+                i,
+                (contour.climax(progress).powi(4) * strong * 100.0) as u32 + 1,
+            )
+        }),
+    )
+}
+
+fn melody_step(
+    rng: &mut StdRng,
+    (position, interval): (i32, i32),
+    (low, high): (i32, i32),
+    target: f64,
+    chord: Option<&Chord>,
+) -> i32 {
+    let recover = interval.abs() >= 3;
+    weighted(
+        rng,
+        (low..=high).map(|candidate| {
+            let step = candidate - position;
+            let mut weight = match step.abs() {
+                0 => 2.0,
+                1 => 10.0,
+                2 => 4.0,
+                3..=4 => 1.0,
+                5..=7 => 0.3,
+                _ => 0.0,
+            };
+            if recover && (step == 0 || step.abs() > 2 || step.signum() == interval.signum()) {
+                weight *= 0.02;
+            }
+            if chord.is_some_and(|x| x.contains(candidate.rem_euclid(7) as u8)) {
+                weight *= 4.0;
+            }
+            weight /= 1.0 + 0.3 * (candidate as f64 - target).abs();
+            (candidate, (weight * 1000.0) as u32 + 1)
+        }),
+    )
+}
+
+fn cadence_position(position: i32, (low, high): (i32, i32), cadence: Cadence) -> i32 {
+    (low..=high)
+        .filter(|x| cadence.degrees().contains(&(x.rem_euclid(7) as u8)))
+        .min_by_key(|x| (x - position).abs())
+        .unwrap_or(position)
+}
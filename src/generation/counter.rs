@@ -0,0 +1,184 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+use super::harmony::weighted;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CounterStyle {
+    Contrary,
+    Thirds,
+    Sixths,
+}
+
+pub fn generate_counter_melody<const N: usize>(
+    rng: &mut StdRng,
+    style: CounterStyle,
+    range: (i32, i32),
+    lead: usize,
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    match style {
+        CounterStyle::Contrary => contrary_line(rng, range, lead, voice, bars),
+        CounterStyle::Thirds => parallel_line(2, range, lead, voice, bars),
+        CounterStyle::Sixths => parallel_line(5, range, lead, voice, bars),
+    }
+}
+
+fn lead_line<const N: usize>(bar: &Bar<N>, lead: usize) -> Vec<(Rational, Rational, i32)> {
+    let mut line: Vec<(Rational, Rational, i32)> = Vec::new();
+    for (start, note) in bar.notes[lead]
+        .iter()
+        .filter(|x| x.1.drum.is_none() && !x.1.doubling)
+    {
+        let position = note.octave as i32 * 7 + note.pitch as i32;
+        match line.iter_mut().find(|x| x.0 == *start) {
+            Some(existing) if existing.2 < position => *existing = (*start, note.length, position),
+            Some(_) => (),
+            None => line.push((*start, note.length, position)),
+        }
+    }
+    line.sort_by_key(|x| x.0);
+    line
+}
+
+fn parallel_line<const N: usize>(
+    interval: i32,
+    (low, high): (i32, i32),
+    lead: usize,
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    for bar in bars.iter_mut() {
+        for (start, length, position) in lead_line(bar, lead) {
+            let mut position = position - interval;
+            while position < low {
+                position += 7;
+            }
+            while position > high {
+                position -= 7;
+            }
+            let note = Note::new(
+                length,
+                position.rem_euclid(7) as u8,
+                position.div_euclid(7) as u8,
+                bar.accidental(position.rem_euclid(7) as u8),
+            );
+            bar.add_note(voice, start, note);
+        }
+        bar.tuplets[voice] = bar.tuplets[lead].clone();
+    }
+}
+
+fn contrary_line<const N: usize>(
+    rng: &mut StdRng,
+    (low, high): (i32, i32),
+    lead: usize,
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    let half = Rational::new(1, 2);
+    let mut position = (low + high) / 2;
+    let mut previous_lead: Option<i32> = None;
+
+    for bar in bars.iter_mut() {
+        let line = lead_line(bar, lead);
+        let sounding = |time: Rational| line.iter().rev().find(|x| x.0 <= time).map(|x| x.2);
+
+        let slots = bar.beats as i64 * 2;
+        let onsets: Vec<i64> = (0..slots)
+            .filter(|&slot| {
+                let time = Rational::new(slot, 2);
+                let attacked = line.iter().any(|x| x.0 >= time && x.0 < time + half);
+                match slot {
+                    0 => true,
+                    _ if !attacked => rng.gen_bool(0.7),
+                    _ if slot % 4 == 0 => rng.gen_bool(0.4),
+                    _ => rng.gen_bool(0.1),
+                }
+            })
+            .collect();
+
+        for (i, &slot) in onsets.iter().enumerate() {
+            let time = Rational::new(slot, 2);
+            let end = onsets.get(i + 1).map_or(slots, |&x| x);
+            let length = Rational::new(end - slot, 2);
+            let strong = slot % 2 == 0;
+            let lead_position = sounding(time).or(previous_lead);
+            let motion = match (lead_position, previous_lead) {
+                (Some(a), Some(b)) => (a - b).signum(),
+                _ => 0,
+            };
+            let ceiling = lead_position.map_or(high, |x| (x - 1).min(high)).max(low);
+            let next = counter_step(
+                rng,
+                (position, motion),
+                (low, ceiling),
+                lead_position,
+                bar.chord.as_ref(),
+                strong,
+            );
+            position = next;
+            previous_lead = lead_position;
+
+            bar.add_note(
+                voice,
+                time,
+                Note::new(
+                    length,
+                    position.rem_euclid(7) as u8,
+                    position.div_euclid(7) as u8,
+                    bar.accidental(position.rem_euclid(7) as u8),
+                ),
+            );
+        }
+    }
+}
+
+fn counter_step(
+    rng: &mut StdRng,
+    (position, motion): (i32, i32),
+    (low, high): (i32, i32),
+    lead: Option<i32>,
+    chord: Option<&Chord>,
+    strong: bool,
+) -> i32 {
+    weighted(
+        rng,
+        (low..=high).map(|candidate| {
+            let step = candidate - position;
+            let mut weight = match step.abs() {
+                0 => 1.0,
+                1 => 8.0,
+                2 => 4.0,
+                3..=4 => 1.0,
+                _ => 0.1,
+            };
+            if motion != 0 && step.signum() == -motion {
+                weight *= 4.0;
+            } else if motion != 0 && step.signum() == motion {
+                weight *= 0.3;
+            }
+            let interval = lead.map(|x| (x - candidate).rem_euclid(7));
+            let consonant: f64 = match interval {
+                Some(2 | 5) => 6.0,
+                Some(4) => 2.0,
+                Some(0) => 1.0,
+                Some(_) => 0.02,
+                None => 1.0,
+            };
+            if strong {
+                weight *= consonant;
+                if chord.is_some_and(|x| x.contains(candidate.rem_euclid(7) as u8)) {
+                    weight *= 2.0;
+                }
+            } else if step.abs() == 1 {
+                weight *= 3.0;
+            } else {
+                weight *= consonant.sqrt();
+            }
+            (candidate, (weight * 1000.0) as u32 + 1)
+        }),
+    )
+}
@@ -0,0 +1,338 @@
+use std::fmt;
+
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rule {
+    ParallelFifths,
+    ParallelOctaves,
+    DirectFifths,
+    VoiceCrossing,
+    LeadingTone,
+}
+
+impl fmt::Display for Rule {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::ParallelFifths => "parallel fifths",
+            Self::ParallelOctaves => "parallel octaves",
+            Self::DirectFifths => "direct fifths",
+            Self::VoiceCrossing => "voice crossing",
+            Self::LeadingTone => "unresolved leading tone",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct RuleSet {
+    rules: Vec<Rule>,
+    attempts: usize,
+}
+
+impl RuleSet {
+    pub fn new(rules: &[Rule]) -> Self {
+        Self {
+            rules: rules.to_vec(),
+            attempts: 8,
+        }
+    }
+    pub fn strict() -> Self {
+        Self::new(&[
+            Rule::ParallelFifths,
+            Rule::ParallelOctaves,
+            Rule::DirectFifths,
+            Rule::VoiceCrossing,
+            Rule::LeadingTone,
+        ])
+    }
+    pub fn pop() -> Self {
+        Self::new(&[Rule::ParallelOctaves, Rule::VoiceCrossing])
+    }
+    pub fn with_attempts(mut self, attempts: usize) -> Self {
+        self.attempts = attempts;
+        self
+    }
+    fn has(&self, rule: Rule) -> bool {
+        self.rules.contains(&rule)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Violation {
+    pub rule: Rule,
+    pub bar: usize,
+    pub offset: Rational,
+    pub voices: (usize, usize),
+}
+
+impl fmt::Display for Violation {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "bar {} beat {}: {} in ",
+            self.bar + 1,
+            self.offset + Rational::ONE,
+            self.rule
+        )?;
+        match self.voices {
+            (a, b) if a == b => write!(f, "voice {}", a),
+            (a, b) => write!(f, "voices {} and {}", a, b),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct Sounding {
+    midi: f64,
+    pitch: u8,
+}
+
+struct Event {
+    bar: usize,
+    offset: Rational,
+    voices: Vec<Option<Sounding>>,
+}
+
+fn events<const N: usize>(bars: &[Bar<N>], voices: &[usize]) -> Vec<Event> {
+    let mut events = Vec::new();
+    for (i, bar) in bars.iter().enumerate() {
+        let mut onsets: Vec<Rational> = voices
+            .iter()
+            .flat_map(|&voice| bar.notes[voice].iter().map(|x| x.0))
+            .collect();
+        onsets.sort();
+        onsets.dedup();
+        for offset in onsets {
+            let voices = voices
+                .iter()
+                .map(|&voice| {
+                    bar.notes[voice]
+                        .iter()
+                        .filter(|(start, note)| {
+                            note.drum.is_none()
+                                && !note.doubling
+                                && *start <= offset
+                                && offset < *start + note.length
+                        })
+                        .map(|(_, note)| Sounding {
+                            midi: note.midi(&bar.key),
+                            pitch: note.pitch,
+                        })
+                        .max_by(|a, b| a.midi.total_cmp(&b.midi))
+                })
+                .collect();
+            events.push(Event {
+                bar: i,
+                offset,
+                voices,
+            });
+        }
+    }
+    events
+}
+
+pub fn check<const N: usize>(rules: &RuleSet, bars: &[Bar<N>], voices: &[usize]) -> Vec<Violation> {
+    let events = events(bars, voices);
+    let mut violations = Vec::new();
+    let mut report = |rule: Rule, event: &Event, pair: (usize, usize)| {
+        if rules.has(rule) {
+            violations.push(Violation {
+                rule,
+                bar: event.bar,
+                offset: event.offset,
+                voices: (voices[pair.0], voices[pair.1]),
+            });
+        }
+    };
+
+    for (i, event) in events.iter().enumerate() {
+        for upper in 0..voices.len() {
+            for lower in upper + 1..voices.len() {
+                let (Some(a1), Some(b1)) = (event.voices[upper], event.voices[lower]) else {
+                    continue;
+                };
+                if a1.midi < b1.midi {
+                    report(Rule::VoiceCrossing, event, (upper, lower));
+                }
+                let Some(previous) = i.checked_sub(1).map(|x| &events[x]) else {
+                    continue;
+                };
+                let (Some(a0), Some(b0)) = (previous.voices[upper], previous.voices[lower]) else {
+                    continue;
+                };
+                let (upper_motion, lower_motion) = (a1.midi - a0.midi, b1.midi - b0.midi);
+                if upper_motion == 0.0 || lower_motion == 0.0 {
+                    continue;
+                }
+                let similar = upper_motion.signum() == lower_motion.signum();
+                let before = (a0.midi - b0.midi).rem_euclid(12.0);
+                let after = (a1.midi - b1.midi).rem_euclid(12.0);
+                if before == 7.0 && after == 7.0 && similar {
+                    report(Rule::ParallelFifths, event, (upper, lower));
+                } else if before == 0.0 && after == 0.0 && similar {
+                    report(Rule::ParallelOctaves, event, (upper, lower));
+                } else if similar && (after == 0.0 || after == 7.0) && upper_motion.abs() > 2.0 {
+                    report(Rule::DirectFifths, event, (upper, lower));
+                }
+            }
+        }
+
+        let Some(next) = events.get(i + 1) else {
+            continue;
+        };
+        let bar = &bars[event.bar];
+        let dominant = bar
+            .chord
+            .as_ref()
+            .is_some_and(|x| x.function() == Function::Dominant);
+        if !dominant || bar.key.interval(6, 1) != 1 {
+            continue;
+        }
+        for voice in 0..voices.len() {
+            if let (Some(now), Some(then)) = (event.voices[voice], next.voices[voice]) {
+                if now.pitch == 6 && now.midi != then.midi && then.pitch != 0 {
+                    report(Rule::LeadingTone, event, (voice, voice));
+                }
+            }
+        }
+    }
+    violations
+}
+
+pub fn enforce<const N: usize>(
+    rng: &mut StdRng,
+    rules: &RuleSet,
+    voice: usize,
+    voices: &[usize],
+    bars: &mut [Bar<N>],
+) {
+    let involved =
+        |violation: &Violation| violation.voices.0 == voice || violation.voices.1 == voice;
+    let count = |bars: &[Bar<N>]| {
+        check(rules, bars, voices)
+            .iter()
+            .filter(|x| involved(x))
+            .count()
+    };
+    let mut best: Vec<Vec<(Rational, Note)>> =
+        bars.iter().map(|x| x.notes[voice].clone()).collect();
+    let mut best_count = count(bars);
+
+    for _ in 0..rules.attempts {
+        if best_count == 0 {
+            break;
+        }
+        let violations: Vec<Violation> = check(rules, bars, voices)
+            .into_iter()
+            .filter(involved)
+            .collect();
+        for violation in violations {
+            let bar = &mut bars[violation.bar];
+            let chord = bar.chord.clone();
+            let sounding = bar.notes[voice].iter_mut().find(|(start, note)| {
+                !note.doubling
+                    && *start <= violation.offset
+                    && violation.offset < *start + note.length
+            });
+            if let Some((_, note)) = sounding {
+                let position = note.octave as i32 * 7 + note.pitch as i32;
+                let candidates: Vec<i32> = [-2, -1, 1, 2]
+                    .iter()
+                    .map(|step| position + step)
+                    .filter(|x| {
+                        chord
+                            .as_ref()
+                            .is_none_or(|c| c.contains(x.rem_euclid(7) as u8))
+                    })
+                    .collect();
+                let next = match candidates.is_empty() {
+                    true => position + [-1, 1][rng.gen_range(0..2)],
+                    false => candidates[rng.gen_range(0..candidates.len())],
+                };
+                note.pitch = next.rem_euclid(7) as u8;
+                note.octave = next.div_euclid(7) as u8;
+                note.accidental = chord.as_ref().and_then(|x| x.accidental(note.pitch));
+            }
+        }
+
+        let current = count(bars);
+        if current < best_count {
+            best_count = current;
+            best = bars.iter().map(|x| x.notes[voice].clone()).collect();
+        }
+    }
+
+    for (bar, notes) in bars.iter_mut().zip(best) {
+        bar.notes[voice] = notes;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn bars(upper: [(u8, u8); 2], lower: [(u8, u8); 2]) -> Vec<Bar<2>> {
+        let mut bar = Bar::new(4, 120.0, Rc::new(Key::new(0, true)), Dynamic::MezzoForte);
+        for (voice, line) in [upper, lower].into_iter().enumerate() {
+            for (i, (pitch, octave)) in line.into_iter().enumerate() {
+                let note = Note::new(Rational::int(2), pitch, octave, None);
+                bar.notes[voice].push((Rational::int(2 * i as i64), note));
+            }
+        }
+        vec![bar]
+    }
+
+    fn rules(bars: &[Bar<2>]) -> Vec<Rule> {
+        check(&RuleSet::strict(), bars, &[0, 1])
+            .into_iter()
+            .map(|x| x.rule)
+            .collect()
+    }
+
+    #[test]
+    fn finds_parallel_fifths_and_octaves() {
+        assert_eq!(
+            rules(&bars([(4, 4), (5, 4)], [(0, 4), (1, 4)])),
+            [Rule::ParallelFifths]
+        );
+        assert_eq!(
+            rules(&bars([(0, 5), (1, 5)], [(0, 4), (1, 4)])),
+            [Rule::ParallelOctaves]
+        );
+        let violation = check(
+            &RuleSet::pop(),
+            &bars([(0, 5), (1, 5)], [(0, 4), (1, 4)]),
+            &[0, 1],
+        );
+        assert_eq!(violation[0].offset, Rational::int(2));
+        assert_eq!(violation[0].voices, (0, 1));
+    }
+
+    #[test]
+    fn allows_fifths_and_octaves_in_contrary_motion() {
+        assert!(rules(&bars([(4, 4), (1, 5)], [(0, 4), (4, 3)])).is_empty());
+        assert!(rules(&bars([(0, 5), (1, 5)], [(0, 4), (1, 3)])).is_empty());
+    }
+
+    #[test]
+    fn pop_rules_ignore_fifths() {
+        let fifths = bars([(4, 4), (5, 4)], [(0, 4), (1, 4)]);
+        assert!(check(&RuleSet::pop(), &fifths, &[0, 1]).is_empty());
+    }
+
+    #[test]
+    fn enforce_removes_parallel_octaves() {
+        let mut bars = bars([(0, 5), (1, 5)], [(0, 4), (1, 4)]);
+        let rules = RuleSet::pop();
+        enforce(&mut StdRng::seed_from_u64(1), &rules, 0, &[0, 1], &mut bars);
+        assert!(check(&rules, &bars, &[0, 1]).is_empty());
+        assert_eq!(bars[0].notes[1][1].1.pitch, 1);
+    }
+}
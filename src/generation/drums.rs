@@ -0,0 +1,336 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Groove {
+    Rock,
+    FourOnTheFloor,
+    Breakbeat,
+    HalfTime,
+    Trap,
+    March,
+    Waltz,
+}
+
+impl Groove {
+    pub fn fits(&self, beats: u8) -> bool {
+        !patterns(*self, beats).is_empty()
+    }
+}
+
+type Grid = &'static [(Drum, &'static str)];
+
+const PATTERNS: [(Groove, Grid); 13] = [
+    (
+        Groove::Rock,
+        &[
+            (Drum::Kick, "x.....3.x.x....."),
+            (Drum::Snare, "....x.......x..2"),
+            (Drum::ClosedHat, "x.x.x.x.x.x.x.x."),
+        ],
+    ),
+    (
+        Groove::Rock,
+        &[
+            (Drum::Kick, "x.......x.x...3."),
+            (Drum::Snare, "....x..1....x..."),
+            (Drum::Ride, "x.x.x.x.x.x.x.x."),
+        ],
+    ),
+    (
+        Groove::FourOnTheFloor,
+        &[
+            (Drum::Kick, "x...x...x...x..."),
+            (Drum::Clap, "....x.......x..."),
+            (Drum::ClosedHat, "x...x...x...x..."),
+            (Drum::OpenHat, "..x...x...x...x."),
+        ],
+    ),
+    (
+        Groove::FourOnTheFloor,
+        &[
+            (Drum::Kick, "x...x...x...x..."),
+            (Drum::Snare, "....x.......x..."),
+            (Drum::ClosedHat, "x3x3x3x3x3x3x3x3"),
+        ],
+    ),
+    (
+        Groove::Breakbeat,
+        &[
+            (Drum::Kick, "x.x.......x.3..."),
+            (Drum::Snare, "....x..3.x..x..4"),
+            (Drum::ClosedHat, "x.x.x.x.x.x.x.x."),
+        ],
+    ),
+    (
+        Groove::Breakbeat,
+        &[
+            (Drum::Kick, "x.....x...x....."),
+            (Drum::Snare, "....x.......x.3."),
+            (Drum::ClosedHat, "x.x.x.x.x.x.x.5."),
+        ],
+    ),
+    (
+        Groove::HalfTime,
+        &[
+            (Drum::Kick, "x.....x....3...."),
+            (Drum::Snare, "........x......."),
+            (Drum::ClosedHat, "x.x.x.x.x.x.x.x."),
+        ],
+    ),
+    (
+        Groove::HalfTime,
+        &[
+            (Drum::Kick, "x..........x.3.."),
+            (Drum::Snare, "........x......."),
+            (Drum::Ride, "x...x...x...x..."),
+        ],
+    ),
+    (
+        Groove::Trap,
+        &[
+            (Drum::Kick, "x......x..x..3.."),
+            (Drum::Clap, "........x......."),
+            (Drum::ClosedHat, "xxxxxxxr4xxxxxrx"),
+        ],
+    ),
+    (
+        Groove::March,
+        &[
+            (Drum::Kick, "x.......x......."),
+            (Drum::Snare, "x.xxx.x.x.xxx.x."),
+            (Drum::Crash, "........3......."),
+        ],
+    ),
+    (
+        Groove::March,
+        &[
+            (Drum::Kick, "x...x...x...x..."),
+            (Drum::Snare, "..x.rxx...x.rxx."),
+        ],
+    ),
+    (
+        Groove::Waltz,
+        &[
+            (Drum::Kick, "x..........."),
+            (Drum::Snare, "....x...x..."),
+            (Drum::ClosedHat, "x...x...x..."),
+        ],
+    ),
+    (
+        Groove::Waltz,
+        &[
+            (Drum::Kick, "x.....2....."),
+            (Drum::SideStick, "....x...x..."),
+            (Drum::Ride, "x...x.x.x..."),
+        ],
+    ),
+];
+
+const FILLS: [Grid; 3] = [
+    &[(Drum::Snare, "xxxx")],
+    &[
+        (Drum::Kick, "x...x..."),
+        (Drum::HighTom, "xx......"),
+        (Drum::MidTom, "..xx...."),
+        (Drum::LowTom, "....xx.."),
+        (Drum::Snare, "......xx"),
+    ],
+    &[(Drum::Snare, "x.x.xxxx"), (Drum::Kick, "x...x...")],
+];
+
+fn patterns(groove: Groove, beats: u8) -> Vec<Grid> {
+    let steps = beats as usize * 4;
+    PATTERNS
+        .iter()
+        .filter(|x| x.0 == groove)
+        .map(|x| x.1)
+        .filter(|grid| {
+            grid.iter()
+                .all(|x| steps.is_multiple_of(x.1.len()) || x.1.len().is_multiple_of(steps))
+        })
+        .collect()
+}
+
+enum Step {
+    Hit(f64),
+    Roll,
+}
+
+fn step(symbol: char) -> Option<Step> {
+    match symbol {
+        'x' => Some(Step::Hit(1.0)),
+        'r' => Some(Step::Roll),
+        '1'..='9' => Some(Step::Hit(symbol.to_digit(10).unwrap() as f64 / 10.0)),
+        _ => None,
+    }
+}
+
+fn play_grid(
+    rng: &mut StdRng,
+    grid: Grid,
+    start: Rational,
+    steps: usize,
+    intensity: f64,
+    hits: &mut Vec<(Drum, Rational)>,
+) {
+    for &(drum, lane) in grid.iter() {
+        let lane: Vec<char> = lane.chars().collect();
+        for i in 0..steps {
+            let offset = start + Rational::new(i as i64, 4);
+            match step(lane[i % lane.len()]) {
+                Some(Step::Hit(chance))
+                    if chance >= 1.0 || rng.gen_bool((chance * intensity).min(1.0)) =>
+                {
+                    hits.push((drum, offset));
+                }
+                Some(Step::Roll) => {
+                    hits.push((drum, offset));
+                    hits.push((drum, offset + Rational::new(1, 8)));
+                }
+                _ => (),
+            }
+        }
+    }
+}
+
+pub fn generate_beat<const N: usize>(
+    rng: &mut StdRng,
+    groove: Groove,
+    voice: usize,
+    shuffle: bool,
+    bars: &mut [Bar<N>],
+) {
+    let Some(beats) = bars.first().map(|x| x.beats) else {
+        return;
+    };
+    let patterns = patterns(groove, beats);
+    if patterns.is_empty() {
+        return;
+    }
+    let pattern = patterns[rng.gen_range(0..patterns.len())];
+    let bar_count = bars.len();
+    let functions: Vec<Option<Function>> = bars
+        .iter()
+        .map(|x| x.chord.as_ref().map(|x| x.function()))
+        .collect();
+    let energies: Vec<f64> = bars.iter().map(|x| x.energy).collect();
+    let turn = |i: usize| {
+        (i + 1).is_multiple_of(4)
+            && (energies[i] > 0.8
+                || functions[i] == Some(Function::Dominant)
+                    && functions.get(i + 1) == Some(&Some(Function::Tonic)))
+    };
+
+    for (i, bar) in bars.iter_mut().enumerate() {
+        let mut hits = Vec::new();
+        play_grid(
+            rng,
+            pattern,
+            Rational::ZERO,
+            bar.beats as usize * 4,
+            0.5 + bar.energy,
+            &mut hits,
+        );
+        if bar.energy < 0.3 {
+            hits.retain(|(drum, offset)| {
+                offset.fract() == Rational::ZERO
+                    || !matches!(drum, Drum::ClosedHat | Drum::OpenHat | Drum::Ride)
+            });
+        }
+        let pickup = bar.length() - Rational::new(1, 2);
+        if bar.energy > 0.75
+            && !hits.contains(&(Drum::Kick, pickup))
+            && rng.gen_bool(((bar.energy - 0.75) * 4.0).min(1.0))
+        {
+            hits.push((Drum::Kick, pickup));
+        }
+
+        if i == 0 || turn(i - 1) {
+            hits.retain(|(drum, offset)| {
+                *offset != Rational::ZERO || !matches!(drum, Drum::ClosedHat | Drum::Ride)
+            });
+            hits.push((Drum::Crash, Rational::ZERO));
+        }
+
+        let fill = i + 1 == bar_count || turn(i);
+        let mut fill_start = bar.length();
+        let mut triplet = false;
+        if fill {
+            let grid = FILLS[rng.gen_range(0..FILLS.len())];
+            let steps = grid
+                .iter()
+                .map(|x| x.1.len())
+                .max()
+                .unwrap_or(0)
+                .min(bar.beats as usize * 4);
+            triplet = shuffle || rng.gen_bool(0.25);
+            fill_start = if triplet {
+                bar.length() - Rational::ONE
+            } else {
+                bar.length() - Rational::new(steps as i64, 4)
+            }
+            .max(Rational::ZERO);
+            hits.retain(|(_, offset)| *offset < fill_start);
+            if triplet {
+                let tuplet = Tuplet::triplet(fill_start, Rational::ONE);
+                (0..3).for_each(|j| hits.push((Drum::Snare, tuplet.offset(j))));
+                bar.add_tuplet(voice, tuplet);
+            } else {
+                play_grid(rng, grid, fill_start, steps, 1.0, &mut hits);
+            }
+        }
+
+        if shuffle {
+            hits.retain(|(_, offset)| {
+                (*offset * 2).fract() == Rational::ZERO || *offset >= fill_start
+            });
+        }
+        let sixteenths = hits
+            .iter()
+            .filter(|(_, offset)| !triplet || *offset < fill_start)
+            .any(|(_, offset)| (*offset * 2).fract() != Rational::ZERO);
+
+        let swung: Vec<i64> = if shuffle {
+            let mut beats: Vec<i64> = hits
+                .iter()
+                .filter(|(_, offset)| offset.fract() == Rational::new(1, 2))
+                .map(|(_, offset)| offset.floor())
+                .collect();
+            beats.sort();
+            beats.dedup();
+            beats
+        } else {
+            Vec::new()
+        };
+        for &beat in swung.iter() {
+            bar.add_tuplet(voice, Tuplet::triplet(Rational::int(beat), Rational::ONE));
+        }
+
+        let rolls: Vec<(Drum, Rational)> = hits
+            .iter()
+            .filter(|(_, offset)| (*offset * 4).fract() != Rational::ZERO)
+            .map(|&(drum, offset)| (drum, offset - Rational::new(1, 8)))
+            .collect();
+        for (drum, offset) in hits {
+            let (offset, length) = if triplet && offset >= fill_start {
+                (offset, Rational::new(1, 3))
+            } else if swung.contains(&offset.floor()) && offset.fract() == Rational::new(1, 2) {
+                (
+                    Rational::int(offset.floor()) + Rational::new(2, 3),
+                    Rational::new(1, 3),
+                )
+            } else if swung.contains(&offset.floor()) {
+                (offset, Rational::new(2, 3))
+            } else if (offset * 4).fract() != Rational::ZERO || rolls.contains(&(drum, offset)) {
+                (offset, Rational::new(1, 8))
+            } else if sixteenths {
+                (offset, Rational::new(1, 4))
+            } else {
+                (offset, Rational::new(1, 2))
+            };
+            bar.add_note(voice, offset, Note::hit(length, drum));
+        }
+    }
+}
@@ -0,0 +1,145 @@
+use std::fmt;
+
+use anyhow::anyhow;
+
+use crate::score::Dynamic;
+
+use super::form::{Form, Role};
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct EnergyCurve {
+    points: Vec<(f64, f64)>,
+}
+
+impl EnergyCurve {
+    pub fn new(levels: &[f64]) -> Self {
+        let last = levels.len().saturating_sub(1).max(1) as f64;
+        Self::from_points(
+            levels
+                .iter()
+                .enumerate()
+                .map(|(i, &level)| (i as f64 / last, level))
+                .collect(),
+        )
+    }
+    fn from_points(mut points: Vec<(f64, f64)>) -> Self {
+        for point in points.iter_mut() {
+            *point = (point.0.clamp(0.0, 1.0), point.1.clamp(0.0, 1.0));
+        }
+        points.sort_by(|a, b| a.0.total_cmp(&b.0));
+        Self { points }
+    }
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let invalid = || anyhow!("invalid energy curve {}", text);
+        let number = |x: &str| {
+            x.parse::<f64>()
+                .ok()
+                .filter(|x| x.is_finite())
+                .ok_or_else(invalid)
+        };
+        let values: Vec<&str> = text.split_whitespace().collect();
+        if values.is_empty() {
+            return Err(invalid());
+        }
+        if values.iter().all(|x| x.contains(':')) {
+            let points = values
+                .iter()
+                .map(|x| {
+                    let (position, level) = x.split_once(':').ok_or_else(invalid)?;
+                    Ok((number(position)?, number(level)?))
+                })
+                .collect::<Result<_, anyhow::Error>>()?;
+            return Ok(Self::from_points(points));
+        }
+        let levels: Vec<f64> = values.iter().map(|x| number(x)).collect::<Result<_, _>>()?;
+        Ok(Self::new(&levels))
+    }
+    pub fn at(&self, position: f64) -> f64 {
+        let Some(i) = self.points.iter().rposition(|x| x.0 <= position) else {
+            return self.points.first().map_or(0.5, |x| x.1);
+        };
+        match self.points.get(i + 1) {
+            Some(&(end, to)) => {
+                let (start, from) = self.points[i];
+                from + (to - from) * (position - start) / (end - start)
+            }
+            None => self.points[i].1,
+        }
+    }
+    pub fn levels(&self, start: usize, length: usize, total: usize) -> Vec<f64> {
+        (start..start + length)
+            .map(|bar| self.at((bar as f64 + 0.5) / total.max(1) as f64))
+            .collect()
+    }
+}
+
+impl fmt::Display for EnergyCurve {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let points: Vec<String> = self
+            .points
+            .iter()
+            .map(|(position, level)| format!("{:.3}:{:.3}", position, level))
+            .collect();
+        write!(f, "{}", points.join(" "))
+    }
+}
+
+pub fn dynamic(energy: f64) -> Dynamic {
+    match energy {
+        x if x < 0.25 => Dynamic::Piano,
+        x if x < 0.5 => Dynamic::MezzoPiano,
+        x if x < 0.75 => Dynamic::MezzoForte,
+        _ => Dynamic::Forte,
+    }
+}
+
+pub fn generate_curve(form: &Form) -> EnergyCurve {
+    let timeline = form.timeline();
+    let total = timeline.last().map_or(1, |x| x.1 + x.2) as f64;
+    let mut points = Vec::new();
+    for (i, &(section, start, length)) in timeline.iter().enumerate() {
+        let next = timeline.get(i + 1).map_or(section.energy, |x| x.0.energy);
+        let end = match section.role {
+            Role::Build => next,
+            Role::Outro => section.energy - 0.15,
+            Role::Chorus | Role::Drop => section.energy,
+            _ => section.energy + 0.05,
+        };
+        points.push((start as f64 / total, section.energy));
+        points.push(((start + length) as f64 / total, end));
+    }
+    EnergyCurve::from_points(points)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_evenly_spread_levels() {
+        let curve = EnergyCurve::parse("0.2 0.6 1.0").unwrap();
+        assert_eq!(curve.points, [(0.0, 0.2), (0.5, 0.6), (1.0, 1.0)]);
+        assert!((curve.at(0.25) - 0.4).abs() < 1e-9);
+    }
+
+    #[test]
+    fn parses_positioned_levels() {
+        let curve = EnergyCurve::parse("1:0.1 0:0.3 0.5:2").unwrap();
+        assert_eq!(curve.points, [(0.0, 0.3), (0.5, 1.0), (1.0, 0.1)]);
+        assert_eq!(curve.at(1.5), 0.1);
+    }
+
+    #[test]
+    fn rejects_invalid_curves() {
+        for text in [
+            "",
+            "0.2 high",
+            "0:0.2 0.5",
+            "nan",
+            "0.2 inf",
+            "0:0.1 NaN:0.5",
+        ] {
+            assert!(EnergyCurve::parse(text).is_err(), "{}", text);
+        }
+    }
+}
@@ -0,0 +1,436 @@
+use std::time::{Duration, Instant};
+
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+use super::{fix_ties, nearest_degree};
+
+type Line = Vec<(Vec<(Rational, Note)>, Vec<Tuplet>)>;
+
+struct Sound {
+    offset: Rational,
+    length: Rational,
+    position: i32,
+    pitch: u8,
+    accidental: Option<bool>,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Fitness {
+    chord_tones: f64,
+    smoothness: f64,
+    repetition: f64,
+    range: f64,
+    variety: f64,
+}
+
+impl Default for Fitness {
+    fn default() -> Self {
+        Self {
+            chord_tones: 1.0,
+            smoothness: 1.0,
+            repetition: 0.5,
+            range: 1.0,
+            variety: 0.5,
+        }
+    }
+}
+
+impl Fitness {
+    pub fn new(
+        chord_tones: f64,
+        smoothness: f64,
+        repetition: f64,
+        range: f64,
+        variety: f64,
+    ) -> Self {
+        Self {
+            chord_tones,
+            smoothness,
+            repetition,
+            range,
+            variety,
+        }
+    }
+    fn score<const N: usize>(&self, range: (i32, i32), bars: &[Bar<N>], line: &Line) -> f64 {
+        let tops: Vec<Vec<Sound>> = line
+            .iter()
+            .map(|(notes, _)| {
+                let mut top: Vec<Sound> = Vec::new();
+                for (offset, note) in notes.iter().filter(|x| x.1.drum.is_none()) {
+                    let sound = Sound {
+                        offset: *offset,
+                        length: note.length,
+                        position: note.octave as i32 * 7 + note.pitch as i32,
+                        pitch: note.pitch,
+                        accidental: note.accidental,
+                    };
+                    match top.iter_mut().find(|x| x.offset == *offset) {
+                        Some(x) if x.position < sound.position => *x = sound,
+                        Some(_) => (),
+                        None => top.push(sound),
+                    }
+                }
+                top.sort_by_key(|x| x.offset);
+                top
+            })
+            .collect();
+        let notes: Vec<&Sound> = tops.iter().flatten().collect();
+        if notes.is_empty() {
+            return 0.0;
+        }
+
+        let mut weight = 0.0;
+        let mut aligned = 0.0;
+        for (bar, top) in bars.iter().zip(tops.iter()) {
+            for sound in top.iter() {
+                let strong = match sound.offset.fract() == Rational::ZERO {
+                    true => 2.0,
+                    false => 1.0,
+                };
+                let duration = sound.length.as_f64() * strong;
+                weight += duration;
+                let consonant = match &bar.chord {
+                    Some(chord) => {
+                        chord.contains(sound.pitch)
+                            && chord.accidental(sound.pitch) == sound.accidental
+                    }
+                    None => sound.accidental.is_none(),
+                };
+                if consonant {
+                    aligned += duration;
+                }
+            }
+        }
+        let chord_tones = aligned / weight;
+
+        let leaps: Vec<f64> = notes
+            .windows(2)
+            .map(|x| {
+                let leap = (x[1].position - x[0].position).abs();
+                1.0 - ((leap - 2).max(0) as f64 / 5.0).min(1.0)
+            })
+            .collect();
+        let smoothness = match leaps.is_empty() {
+            true => 1.0,
+            false => leaps.iter().sum::<f64>() / leaps.len() as f64,
+        };
+
+        let rhythm = |top: &[Sound]| top.iter().map(|x| (x.offset, x.length)).collect::<Vec<_>>();
+        let intervals = |top: &[Sound]| {
+            top.windows(2)
+                .map(|x| x[1].position - x[0].position)
+                .collect::<Vec<_>>()
+        };
+        let similarity = |a: &[Sound], b: &[Sound]| {
+            if rhythm(a) != rhythm(b) {
+                let shared = a
+                    .iter()
+                    .filter(|x| b.iter().any(|y| y.offset == x.offset))
+                    .count();
+                let total = a.len() + b.len() - shared;
+                return 0.5 * shared as f64 / total.max(1) as f64;
+            }
+            match intervals(a) == intervals(b) {
+                true => 1.0,
+                false => 0.5,
+            }
+        };
+        let repetition = match tops.len() {
+            0 | 1 => 0.0,
+            count => {
+                (1..count)
+                    .map(|i| {
+                        (0..i)
+                            .map(|j| similarity(&tops[i], &tops[j]))
+                            .fold(0.0, f64::max)
+                    })
+                    .sum::<f64>()
+                    / (count - 1) as f64
+            }
+        };
+
+        let (low, high) = range;
+        let inside = notes
+            .iter()
+            .filter(|x| low <= x.position && x.position <= high)
+            .count();
+        let range = inside as f64 / notes.len() as f64;
+
+        let mut durations: Vec<Rational> = notes.iter().map(|x| x.length).collect();
+        durations.sort();
+        durations.dedup();
+        let mut rhythms: Vec<Vec<(Rational, Rational)>> = tops.iter().map(|x| rhythm(x)).collect();
+        rhythms.sort();
+        rhythms.dedup();
+        let variety = 0.5 * (durations.len() as f64 / 4.0).min(1.0)
+            + 0.5 * rhythms.len() as f64 / tops.len() as f64;
+
+        let total =
+            self.chord_tones + self.smoothness + self.repetition + self.range + self.variety;
+        if total <= 0.0 {
+            return 0.0;
+        }
+        (self.chord_tones * chord_tones
+            + self.smoothness * smoothness
+            + self.repetition * repetition
+            + self.range * range
+            + self.variety * variety)
+            / total
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Evolution {
+    population: usize,
+    generations: usize,
+    time_limit: Option<Duration>,
+    fitness: Fitness,
+}
+
+impl Evolution {
+    pub fn new(generations: usize) -> Self {
+        Self {
+            population: 16,
+            generations,
+            time_limit: None,
+            fitness: Fitness::default(),
+        }
+    }
+    pub fn with_population(mut self, population: usize) -> Self {
+        self.population = population.max(2);
+        self
+    }
+    pub fn with_time_limit(mut self, time_limit: Duration) -> Self {
+        self.time_limit = Some(time_limit);
+        self
+    }
+    pub fn with_fitness(mut self, fitness: Fitness) -> Self {
+        self.fitness = fitness;
+        self
+    }
+}
+
+struct Candidate {
+    line: Line,
+    fitness: f64,
+}
+
+pub fn evolve_melody<const N: usize>(
+    rng: &mut StdRng,
+    evolution: &Evolution,
+    range: (i32, i32),
+    voice: usize,
+    bars: &mut [Bar<N>],
+    mut seed: impl FnMut(&mut StdRng, &mut [Bar<N>]),
+) {
+    let start = Instant::now();
+    let mut template = bars.to_vec();
+    for bar in template.iter_mut() {
+        bar.notes[voice].clear();
+        bar.tuplets[voice].clear();
+    }
+    let evaluate = |line: Line| Candidate {
+        fitness: evolution.fitness.score(range, &template, &line),
+        line,
+    };
+
+    let mut population: Vec<Candidate> = (0..evolution.population)
+        .map(|_| {
+            let mut candidate = template.clone();
+            seed(rng, &mut candidate);
+            evaluate(
+                candidate
+                    .into_iter()
+                    .map(|x| (x.notes[voice].clone(), x.tuplets[voice].clone()))
+                    .collect(),
+            )
+        })
+        .collect();
+    population.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+
+    for _ in 0..evolution.generations {
+        if evolution.time_limit.is_some_and(|x| start.elapsed() >= x) {
+            break;
+        }
+        let mut next: Vec<Candidate> = population.drain(..2.min(population.len())).collect();
+        let parents = next.iter().chain(population.iter()).collect::<Vec<_>>();
+        let mut children = Vec::new();
+        while next.len() + children.len() < evolution.population {
+            let a = tournament(rng, &parents);
+            let mut line = match rng.gen_bool(0.7) {
+                true => {
+                    let b = tournament(rng, &parents);
+                    crossover(rng, &a.line, &b.line)
+                }
+                false => a.line.clone(),
+            };
+            for _ in 0..rng.gen_range(1..=2) {
+                mutate(rng, &template, &mut line);
+            }
+            children.push(evaluate(line));
+        }
+        next.extend(children);
+        population = next;
+        population.sort_by(|a, b| b.fitness.total_cmp(&a.fitness));
+    }
+
+    let best = population.swap_remove(0);
+    for (bar, (notes, tuplets)) in bars.iter_mut().zip(best.line) {
+        bar.notes[voice] = notes;
+        bar.tuplets[voice] = tuplets;
+    }
+    fix_ties(voice, bars);
+}
+
+fn tournament<'a>(rng: &mut StdRng, population: &[&'a Candidate]) -> &'a Candidate {
+    (0..3)
+        .map(|_| population[rng.gen_range(0..population.len())])
+        .max_by(|a, b| a.fitness.total_cmp(&b.fitness))
+        .unwrap()
+}
+
+fn crossover(rng: &mut StdRng, a: &Line, b: &Line) -> Line {
+    if a.len() < 2 {
+        return a.clone();
+    }
+    let point = rng.gen_range(1..a.len());
+    a[..point]
+        .iter()
+        .chain(b[point..].iter())
+        .cloned()
+        .collect()
+}
+
+fn mutate<const N: usize>(rng: &mut StdRng, bars: &[Bar<N>], line: &mut Line) {
+    let i = rng.gen_range(0..line.len());
+    let mut onsets: Vec<Rational> = line[i].0.iter().map(|x| x.0).collect();
+    onsets.sort();
+    onsets.dedup();
+    if onsets.is_empty() {
+        return;
+    }
+    let onset = onsets[rng.gen_range(0..onsets.len())];
+    let shift = |line: &mut Line, onset: Rational, steps: i32| {
+        for (_, note) in line[i].0.iter_mut().filter(|x| x.0 == onset) {
+            let position = note.octave as i32 * 7 + note.pitch as i32 + steps;
+            if position < 0 {
+                continue;
+            }
+            note.pitch = position.rem_euclid(7) as u8;
+            note.octave = position.div_euclid(7) as u8;
+            note.accidental = bars[i].accidental(note.pitch);
+        }
+    };
+    let top = |line: &Line, onset: Rational| {
+        line[i]
+            .0
+            .iter()
+            .filter(|x| x.0 == onset)
+            .map(|x| x.1.octave as i32 * 7 + x.1.pitch as i32)
+            .max()
+            .unwrap_or(0)
+    };
+
+    match rng.gen_range(0..4) {
+        0 => shift(line, onset, [-2, -1, 1, 2][rng.gen_range(0..4)]),
+        1 => {
+            let Some(chord) = &bars[i].chord else {
+                return;
+            };
+            let position = top(line, onset);
+            let target = chord
+                .tones()
+                .iter()
+                .map(|&x| nearest_degree(position, x as i32))
+                .min_by_key(|x| (x - position).abs())
+                .unwrap_or(position);
+            shift(line, onset, target - position);
+        }
+        2 => {
+            let sources: Vec<usize> = (0..line.len())
+                .filter(|&j| j != i && bars[j].beats == bars[i].beats && !line[j].0.is_empty())
+                .collect();
+            if sources.is_empty() {
+                return;
+            }
+            let j = sources[rng.gen_range(0..sources.len())];
+            let degree = |bar: &Bar<N>| bar.chord.as_ref().map_or(0, |x| x.degree as i32);
+            let steps = (degree(&bars[i]) - degree(&bars[j]) + 3).rem_euclid(7) - 3;
+            line[i] = line[j].clone();
+            let mut onsets: Vec<Rational> = line[i].0.iter().map(|x| x.0).collect();
+            onsets.sort();
+            onsets.dedup();
+            onsets.into_iter().for_each(|x| shift(line, x, steps));
+        }
+        _ => {
+            let other = onsets[rng.gen_range(0..onsets.len())];
+            let steps = top(line, other) - top(line, onset);
+            shift(line, onset, steps);
+            shift(line, other, -steps);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use rand::SeedableRng;
+
+    use super::*;
+
+    fn progression() -> Vec<Bar<1>> {
+        let key = Rc::new(Key::new(0, true));
+        [0, 3, 4, 0]
+            .into_iter()
+            .map(|degree| {
+                let mut bar = Bar::new(4, 120.0, key.clone(), Dynamic::MezzoForte);
+                bar.chord = Some(Chord::new(degree, false));
+                bar
+            })
+            .collect()
+    }
+
+    fn write(rng: &mut StdRng, bars: &mut [Bar<1>]) {
+        for bar in bars.iter_mut() {
+            for beat in 0..bar.beats as i64 {
+                let note = Note::new(
+                    Rational::ONE,
+                    rng.gen_range(0..7),
+                    rng.gen_range(4..6),
+                    None,
+                );
+                bar.notes[0].push((Rational::int(beat), note));
+            }
+        }
+    }
+
+    fn best(seed: u64, generations: usize) -> f64 {
+        let evolution = Evolution::new(generations).with_population(8);
+        let mut bars = progression();
+        evolve_melody(
+            &mut StdRng::seed_from_u64(seed),
+            &evolution,
+            (28, 40),
+            0,
+            &mut bars,
+            write,
+        );
+        let line = bars
+            .iter()
+            .map(|x| (x.notes[0].clone(), x.tuplets[0].clone()))
+            .collect();
+        evolution.fitness.score((28, 40), &progression(), &line)
+    }
+
+    #[test]
+    fn never_loses_fitness_of_the_starting_population() {
+        for seed in 0..8 {
+            let start = best(seed, 0);
+            let evolved = best(seed, 30);
+            assert!(evolved >= start, "seed {}: {} < {}", seed, evolved, start);
+        }
+    }
+}
@@ -0,0 +1,506 @@
+use std::{fmt, path::Path};
+
+use anyhow::anyhow;
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    export::save_file,
+    score::{Dynamic, Part},
+};
+
+use super::{
+    chart::Chart,
+    energy::{dynamic, EnergyCurve},
+};
+
+const OUTRO_CEILING: f64 = 0.45;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormKind {
+    VerseChorus,
+    Aaba,
+    Rondo,
+    ThroughComposed,
+    BuildDrop,
+}
+
+impl FormKind {
+    const ALL: [Self; 5] = [
+        Self::VerseChorus,
+        Self::Aaba,
+        Self::Rondo,
+        Self::ThroughComposed,
+        Self::BuildDrop,
+    ];
+    pub fn parse(name: &str) -> Result<Self, anyhow::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.to_string() == name)
+            .ok_or(anyhow!("unknown form {}", name))
+    }
+    pub fn random(rng: &mut StdRng) -> Self {
+        Self::ALL[rng.gen_range(0..Self::ALL.len())]
+    }
+}
+
+impl fmt::Display for FormKind {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::VerseChorus => "verse_chorus",
+            Self::Aaba => "aaba",
+            Self::Rondo => "rondo",
+            Self::ThroughComposed => "through_composed",
+            Self::BuildDrop => "build_drop",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Role {
+    Intro,
+    Verse,
+    Chorus,
+    Bridge,
+    Episode,
+    Build,
+    Drop,
+    Outro,
+}
+
+impl Role {
+    const ALL: [Self; 8] = [
+        Self::Intro,
+        Self::Verse,
+        Self::Chorus,
+        Self::Bridge,
+        Self::Episode,
+        Self::Build,
+        Self::Drop,
+        Self::Outro,
+    ];
+    fn parse(name: &str) -> Result<Self, anyhow::Error> {
+        Self::ALL
+            .into_iter()
+            .find(|x| x.to_string() == name)
+            .ok_or(anyhow!("unknown section role {}", name))
+    }
+    fn lengths(&self) -> &'static [usize] {
+        match self {
+            Self::Intro | Self::Outro => &[2, 4, 4],
+            Self::Verse | Self::Drop => &[8, 8, 16],
+            Self::Chorus => &[8],
+            Self::Bridge | Self::Episode | Self::Build => &[4, 8],
+        }
+    }
+    fn energy(&self) -> (f64, f64) {
+        match self {
+            Self::Intro => (0.2, 0.4),
+            Self::Verse => (0.35, 0.55),
+            Self::Chorus => (0.65, 0.85),
+            Self::Bridge => (0.4, 0.6),
+            Self::Episode => (0.4, 0.7),
+            Self::Build => (0.5, 0.7),
+            Self::Drop => (0.85, 1.0),
+            Self::Outro => (0.15, 0.3),
+        }
+    }
+}
+
+impl fmt::Display for Role {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let name = match self {
+            Self::Intro => "intro",
+            Self::Verse => "verse",
+            Self::Chorus => "chorus",
+            Self::Bridge => "bridge",
+            Self::Episode => "episode",
+            Self::Build => "build",
+            Self::Drop => "drop",
+            Self::Outro => "outro",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FormSection {
+    pub name: String,
+    pub role: Role,
+    pub length: usize,
+    pub energy: f64,
+    pub dynamic: Dynamic,
+    pub variation_of: Option<String>,
+    pub chart: Option<Chart>,
+    pub levels: Vec<f64>,
+}
+
+impl FormSection {
+    fn new(name: &str, role: Role, length: usize, energy: f64) -> Self {
+        let energy = energy.clamp(0.0, 1.0);
+        Self {
+            name: name.to_string(),
+            role,
+            length,
+            energy,
+            dynamic: dynamic(energy),
+            variation_of: None,
+            chart: None,
+            levels: Vec::new(),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Form {
+    pub kind: FormKind,
+    pub sections: Vec<FormSection>,
+    pub order: Vec<String>,
+    pub curve: Option<EnergyCurve>,
+}
+
+impl Form {
+    fn new(kind: FormKind) -> Self {
+        Self {
+            kind,
+            sections: Vec::new(),
+            order: Vec::new(),
+            curve: None,
+        }
+    }
+    fn section(&self, name: &str) -> Option<&FormSection> {
+        self.sections.iter().find(|x| x.name == name)
+    }
+    fn add(&mut self, rng: &mut StdRng, name: &str, role: Role) {
+        if self.section(name).is_none() {
+            let lengths = role.lengths();
+            let (low, high) = role.energy();
+            self.sections.push(FormSection::new(
+                name,
+                role,
+                lengths[rng.gen_range(0..lengths.len())],
+                rng.gen_range(low..=high),
+            ));
+        }
+        self.order.push(name.to_string());
+    }
+    fn repeat(&mut self, rng: &mut StdRng, name: &str, variation: f64) {
+        let latest = self
+            .order
+            .iter()
+            .rev()
+            .find(|x| x.trim_end_matches('\'') == name)
+            .cloned()
+            .unwrap_or(name.to_string());
+        if !rng.gen_bool(variation) {
+            self.order.push(latest);
+            return;
+        }
+        let original = self.section(&latest).unwrap().clone();
+        let mut varied = FormSection::new(
+            &format!("{}'", latest),
+            original.role,
+            original.length,
+            original.energy + rng.gen_range(0.0..=0.1),
+        );
+        varied.chart = original.chart;
+        varied.variation_of = Some(original.variation_of.unwrap_or(original.name));
+        self.order.push(varied.name.clone());
+        self.sections.push(varied);
+    }
+    pub fn timeline(&self) -> Vec<(&FormSection, usize, usize)> {
+        let mut start = 0;
+        let mut timeline = Vec::new();
+        for name in self.order.iter() {
+            if let Some(section) = self.section(name) {
+                timeline.push((section, start, section.length));
+                start += section.length;
+            }
+        }
+        timeline
+    }
+    pub fn apply_curve(&mut self, curve: EnergyCurve) {
+        let timeline: Vec<(String, usize, usize)> = self
+            .timeline()
+            .into_iter()
+            .map(|(section, start, length)| (section.name.clone(), start, length))
+            .collect();
+        let total = timeline.last().map_or(0, |x| x.1 + x.2);
+        let mean = |levels: &[f64]| levels.iter().sum::<f64>() / levels.len().max(1) as f64;
+        let mut applied: Vec<String> = Vec::new();
+        let mut splits: Vec<(String, String)> = Vec::new();
+        for (i, (name, start, length)) in timeline.into_iter().enumerate() {
+            let Some(section) = self.section(&name).cloned() else {
+                continue;
+            };
+            let mut levels = curve.levels(start, length, total);
+            if section.role == Role::Outro {
+                levels.iter_mut().for_each(|x| *x = x.min(OUTRO_CEILING));
+            }
+            let energy = mean(&levels);
+            let matches =
+                |x: &FormSection| (x.energy - energy).abs() <= 0.1 && x.dynamic == dynamic(energy);
+            let target = if !applied.contains(&name) {
+                name
+            } else if matches(&section) {
+                continue;
+            } else if let Some((_, split)) = splits
+                .iter()
+                .find(|x| x.0 == name && self.section(&x.1).is_some_and(matches))
+            {
+                self.order[i] = split.clone();
+                continue;
+            } else {
+                let mut varied = section.clone();
+                while self.section(&varied.name).is_some() {
+                    varied.name.push('\'');
+                }
+                varied.variation_of = Some(section.variation_of.unwrap_or(section.name));
+                self.order[i] = varied.name.clone();
+                splits.push((name, varied.name.clone()));
+                let target = varied.name.clone();
+                self.sections.push(varied);
+                target
+            };
+            let section = self.sections.iter_mut().find(|x| x.name == target).unwrap();
+            section.energy = energy;
+            section.dynamic = dynamic(energy);
+            section.levels = levels;
+            applied.push(target);
+        }
+        self.curve = Some(curve);
+    }
+    pub fn parts(&self) -> Vec<Part> {
+        let mut parts: Vec<Part> = Vec::new();
+        let mut start = 0;
+        while start < self.order.len() {
+            let name = &self.order[start];
+            let repeats = self.order[start..]
+                .iter()
+                .take_while(|x| *x == name)
+                .count();
+            parts.push(Part::new(&[name]).repeated(repeats).marked(name));
+            start += repeats;
+        }
+        parts
+    }
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut form = Self::new(FormKind::ThroughComposed);
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+            let values: Vec<&str> = line.split_whitespace().collect();
+            match values[0] {
+                "form" => form.kind = FormKind::parse(values.get(1).copied().unwrap_or(""))?,
+                "section" => {
+                    if !(5..=6).contains(&values.len()) {
+                        return Err(anyhow!("invalid form line {}", line));
+                    }
+                    let [name, role, length, energy] = &values[1..5] else {
+                        return Err(anyhow!("invalid form line {}", line));
+                    };
+                    let energy: f64 = energy.parse()?;
+                    if !energy.is_finite() {
+                        return Err(anyhow!("invalid form line {}", line));
+                    }
+                    let mut section =
+                        FormSection::new(name, Role::parse(role)?, length.parse()?, energy);
+                    if let Some(original) = values.get(5) {
+                        match form.section(original) {
+                            None => {
+                                return Err(anyhow!(
+                                    "section {} varies unknown section {}",
+                                    name,
+                                    original
+                                ))
+                            }
+                            Some(x) if x.length != section.length => {
+                                return Err(anyhow!(
+                                    "section {} must have the {} bars of its original {}",
+                                    name,
+                                    x.length,
+                                    original
+                                ))
+                            }
+                            Some(_) => (),
+                        }
+                        section.variation_of = Some(original.to_string());
+                    }
+                    form.sections.push(section);
+                }
+                "chords" => {
+                    let rest = line.split_once(char::is_whitespace).map_or("", |x| x.1);
+                    let (name, chart) = rest
+                        .trim_start()
+                        .split_once(char::is_whitespace)
+                        .ok_or(anyhow!("invalid form line {}", line))?;
+                    let chart = Chart::parse(chart)?;
+                    match form.sections.iter_mut().find(|x| x.name == name) {
+                        Some(section) => section.chart = Some(chart),
+                        None => return Err(anyhow!("chords for unknown section {}", name)),
+                    }
+                }
+                "energy" => {
+                    let rest = line.split_once(char::is_whitespace).map_or("", |x| x.1);
+                    form.curve = Some(EnergyCurve::parse(rest)?);
+                }
+                "order" => form.order = values[1..].iter().map(|x| x.to_string()).collect(),
+                field => return Err(anyhow!("unknown form field {}", field)),
+            }
+        }
+        for i in 0..form.sections.len() {
+            let original = form.sections[i].variation_of.clone();
+            if let (None, Some(original)) = (&form.sections[i].chart, original) {
+                form.sections[i].chart = form.section(&original).and_then(|x| x.chart.clone());
+            }
+        }
+        if let Some(name) = form.order.iter().find(|x| form.section(x).is_none()) {
+            return Err(anyhow!("form order uses unknown section {}", name));
+        }
+        if form.order.is_empty() || form.sections.iter().any(|x| x.length == 0) {
+            return Err(anyhow!(
+                "form needs an order and sections of at least one bar"
+            ));
+        }
+        Ok(form)
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+        save_file(self.to_string(), path)
+    }
+    pub fn find(rng: &mut StdRng, name: &str) -> Result<Self, anyhow::Error> {
+        match FormKind::parse(name) {
+            Ok(kind) => Ok(generate_form(rng, kind)),
+            Err(_) if Path::new(name).exists() => Self::load(name),
+            Err(e) => Err(e),
+        }
+    }
+}
+
+impl fmt::Display for Form {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "form {}", self.kind)?;
+        for section in self.sections.iter() {
+            write!(
+                f,
+                "section {} {} {} {:.2}",
+                section.name, section.role, section.length, section.energy
+            )?;
+            match &section.variation_of {
+                Some(original) => writeln!(f, " {}", original)?,
+                None => writeln!(f)?,
+            }
+        }
+        for section in self.sections.iter() {
+            if let Some(chart) = &section.chart {
+                writeln!(f, "chords {} {}", section.name, chart)?;
+            }
+        }
+        if let Some(curve) = &self.curve {
+            writeln!(f, "energy {}", curve)?;
+        }
+        writeln!(f, "order {}", self.order.join(" "))
+    }
+}
+
+pub fn generate_form(rng: &mut StdRng, kind: FormKind) -> Form {
+    let mut form = Form::new(kind);
+    form.add(rng, "Intro", Role::Intro);
+    match kind {
+        FormKind::VerseChorus => {
+            form.add(rng, "Verse", Role::Verse);
+            form.add(rng, "Chorus", Role::Chorus);
+            form.repeat(rng, "Verse", 0.5);
+            form.repeat(rng, "Chorus", 0.3);
+            if rng.gen_bool(0.6) {
+                form.add(rng, "Bridge", Role::Bridge);
+            }
+            form.repeat(rng, "Chorus", 0.5);
+            if rng.gen_bool(0.4) {
+                form.repeat(rng, "Chorus", 0.0);
+            }
+        }
+        FormKind::Aaba => {
+            form.add(rng, "A", Role::Verse);
+            form.repeat(rng, "A", 0.5);
+            form.add(rng, "B", Role::Bridge);
+            form.repeat(rng, "A", 0.5);
+        }
+        FormKind::Rondo => {
+            form.add(rng, "A", Role::Chorus);
+            form.add(rng, "B", Role::Episode);
+            form.repeat(rng, "A", 0.3);
+            form.add(rng, "C", Role::Episode);
+            form.repeat(rng, "A", 0.3);
+            if rng.gen_bool(0.4) {
+                form.add(rng, "D", Role::Bridge);
+                form.repeat(rng, "A", 0.3);
+            }
+        }
+        FormKind::ThroughComposed => {
+            let roles = [Role::Verse, Role::Episode, Role::Chorus, Role::Bridge];
+            for name in ["A", "B", "C", "D", "E"].iter().take(rng.gen_range(3..=5)) {
+                let role = roles[rng.gen_range(0..roles.len())];
+                form.add(rng, name, role);
+            }
+        }
+        FormKind::BuildDrop => {
+            form.add(rng, "Verse", Role::Verse);
+            form.add(rng, "Build", Role::Build);
+            form.add(rng, "Drop", Role::Drop);
+            form.repeat(rng, "Verse", 0.6);
+            form.repeat(rng, "Build", 0.3);
+            form.repeat(rng, "Drop", 0.5);
+        }
+    }
+    form.add(rng, "Outro", Role::Outro);
+    form
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TEXT: &str = "form aaba
+section A verse 4 0.40
+section B bridge 4 0.60
+section A' verse 4 0.50 A
+chords A | Am | F | C | G7 |
+energy 0.000:0.200 1.000:0.800
+order A A' B A'
+";
+
+    #[test]
+    fn parses_sections_and_order() {
+        let form = Form::parse(TEXT).unwrap();
+        assert_eq!(form.kind, FormKind::Aaba);
+        assert_eq!(form.order, ["A", "A'", "B", "A'"]);
+        let varied = form.section("A'").unwrap();
+        assert_eq!(varied.variation_of.as_deref(), Some("A"));
+        assert_eq!(varied.chart, form.section("A").unwrap().chart);
+        assert!(form.curve.is_some());
+    }
+
+    #[test]
+    fn round_trips_through_display() {
+        let form = Form::parse(TEXT).unwrap();
+        let text = form.to_string();
+        assert!(text.starts_with("form aaba\nsection A verse 4 0.40\n"));
+        assert!(text.contains("chords A' | Am | F | C | G7 |\n"));
+        let reparsed = Form::parse(&text).unwrap();
+        assert_eq!(reparsed.order, form.order);
+        assert_eq!(reparsed.to_string(), text);
+    }
+
+    #[test]
+    fn rejects_invalid_variations() {
+        let longer = TEXT.replace("A' verse 4", "A' verse 8");
+        assert!(Form::parse(&longer).is_err());
+        let extra = TEXT.replace("0.60\n", "0.60 A x\n");
+        assert!(Form::parse(&extra).is_err());
+    }
+}
@@ -0,0 +1,147 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::{Chord, Function};
+
+use super::phrase::Cadence;
+
+const FUNCTIONS: [(Function, Function, u32); 8] = [
+    (Function::Tonic, Function::Tonic, 2),
+    (Function::Tonic, Function::Predominant, 5),
+    (Function::Tonic, Function::Dominant, 2),
+    (Function::Predominant, Function::Predominant, 1),
+    (Function::Predominant, Function::Dominant, 6),
+    (Function::Predominant, Function::Tonic, 1),
+    (Function::Dominant, Function::Tonic, 7),
+    (Function::Dominant, Function::Dominant, 1),
+];
+
+const DEGREES: [(Function, u8, u32); 7] = [
+    (Function::Tonic, 0, 5),
+    (Function::Tonic, 5, 3),
+    (Function::Tonic, 2, 1),
+    (Function::Predominant, 3, 3),
+    (Function::Predominant, 1, 3),
+    (Function::Dominant, 4, 5),
+    (Function::Dominant, 6, 1),
+];
+
+#[derive(Debug, Clone)]
+pub struct Harmony {
+    degrees: Vec<u8>,
+    sevenths: f64,
+}
+
+impl Default for Harmony {
+    fn default() -> Self {
+        Self {
+            degrees: (0..7).collect(),
+            sevenths: 1.0,
+        }
+    }
+}
+
+impl Harmony {
+    pub fn new(degrees: &[u8], sevenths: f64) -> Self {
+        Self {
+            degrees: degrees.iter().map(|x| x % 7).collect(),
+            sevenths,
+        }
+    }
+    fn allows(&self, degree: u8) -> bool {
+        self.degrees.contains(&degree)
+    }
+    fn seventh(&self, rng: &mut StdRng, chance: f64) -> bool {
+        rng.gen_bool((chance * self.sevenths).clamp(0.0, 1.0))
+    }
+}
+
+pub fn generate_progression(
+    rng: &mut StdRng,
+    harmony: &Harmony,
+    length: usize,
+    cadence: Cadence,
+) -> Vec<Chord> {
+    let cadence = match cadence {
+        Cadence::Half if length <= 2 => vec![Chord::new(0, false), Chord::new(4, false)],
+        Cadence::Half => half_cadence(rng, harmony),
+        Cadence::Full => full_cadence(rng, harmony),
+    };
+    if length <= cadence.len() {
+        return cadence[cadence.len() - length..].to_vec();
+    }
+
+    let mut progression = vec![Chord::new(0, false)];
+    while progression.len() + cadence.len() < length {
+        let previous = progression.last().unwrap().clone();
+        progression.push(next_chord(rng, harmony, &previous));
+    }
+    progression.extend(cadence);
+    progression
+}
+
+fn predominant(rng: &mut StdRng, harmony: &Harmony) -> u8 {
+    match (harmony.allows(1), harmony.allows(3)) {
+        (true, false) => 1,
+        (false, true) => 3,
+        _ if rng.gen_bool(0.5) => 1,
+        _ => 3,
+    }
+}
+
+fn half_cadence(rng: &mut StdRng, harmony: &Harmony) -> Vec<Chord> {
+    let predominant = predominant(rng, harmony);
+    vec![
+        Chord::new(predominant, harmony.seventh(rng, 0.3)),
+        Chord::new(4, false),
+    ]
+}
+
+fn full_cadence(rng: &mut StdRng, harmony: &Harmony) -> Vec<Chord> {
+    if rng.gen_bool(0.2) && harmony.allows(3) {
+        return vec![Chord::new(3, false), Chord::new(0, false)];
+    }
+    let predominant = predominant(rng, harmony);
+    vec![
+        Chord::new(predominant, harmony.seventh(rng, 0.3)),
+        Chord::new(4, harmony.seventh(rng, 0.6)),
+        Chord::new(0, false),
+    ]
+}
+
+fn next_chord(rng: &mut StdRng, harmony: &Harmony, previous: &Chord) -> Chord {
+    let candidates = |function: Function| {
+        DEGREES
+            .iter()
+            .filter(move |x| x.0 == function && x.1 != previous.degree && harmony.allows(x.1))
+            .map(|x| (x.1, x.2))
+    };
+    let functions: Vec<(Function, u32)> = FUNCTIONS
+        .iter()
+        .filter(|x| x.0 == previous.function() && candidates(x.1).next().is_some())
+        .map(|x| (x.1, x.2))
+        .collect();
+    if functions.is_empty() {
+        return Chord::new(0, false);
+    }
+    let function = weighted(rng, functions.into_iter());
+    let degree = weighted(rng, candidates(function));
+    let seventh = match function {
+        Function::Dominant => harmony.seventh(rng, 0.5),
+        Function::Predominant => harmony.seventh(rng, 0.3),
+        Function::Tonic => harmony.seventh(rng, 0.1),
+    };
+    Chord::new(degree, seventh)
+}
+
+pub fn weighted<T: Copy>(rng: &mut StdRng, options: impl Iterator<Item = (T, u32)>) -> T {
+    let options: Vec<(T, u32)> = options.collect();
+    let total: u32 = options.iter().map(|x| x.1).sum();
+    let mut choice = rng.gen_range(0..total);
+    for (option, weight) in options.iter() {
+        if choice < *weight {
+            return *option;
+        }
+        choice -= weight;
+    }
+    options.last().unwrap().0
+}
@@ -0,0 +1,291 @@
+use std::{collections::BTreeMap, path::Path};
+
+use anyhow::anyhow;
+use rand::rngs::StdRng;
+
+use crate::{export::save_file, import::ImportedNote, score::*};
+
+use super::harmony::weighted;
+
+const MAX_INTERVAL: i32 = 7;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord)]
+pub struct Symbol {
+    degree: u8,
+    interval: i8,
+    duration: Rational,
+}
+
+impl Symbol {
+    fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let invalid = || anyhow!("invalid symbol {}", text);
+        let mut fields = text.split(':');
+        let mut field = || fields.next().ok_or_else(invalid);
+        let degree = field()?.parse()?;
+        let interval = field()?.parse()?;
+        let duration = field()?;
+        let duration = match duration.split_once('/') {
+            Some((num, den)) => match den.parse()? {
+                0 => return Err(invalid()),
+                den => Rational::new(num.parse()?, den),
+            },
+            None => Rational::int(duration.parse()?),
+        };
+        if degree >= 7 || duration <= Rational::ZERO {
+            return Err(invalid());
+        }
+        Ok(Self {
+            degree,
+            interval,
+            duration,
+        })
+    }
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct MarkovModel {
+    order: usize,
+    table: BTreeMap<Vec<Symbol>, BTreeMap<Symbol, u32>>,
+}
+
+impl MarkovModel {
+    pub fn train(order: usize, lines: &[Vec<ImportedNote>]) -> Self {
+        let mut model = Self {
+            order,
+            table: BTreeMap::new(),
+        };
+        for line in lines {
+            let symbols = to_symbols(line);
+            for i in 0..symbols.len() {
+                for context in 0..=order.min(i) {
+                    *model
+                        .table
+                        .entry(symbols[i - context..i].to_vec())
+                        .or_default()
+                        .entry(symbols[i])
+                        .or_default() += 1;
+                }
+            }
+        }
+        model
+    }
+    pub fn is_empty(&self) -> bool {
+        self.table.is_empty()
+    }
+    fn sample(&self, rng: &mut StdRng, history: &[Symbol]) -> Option<Symbol> {
+        let start = history.len().saturating_sub(self.order);
+        (start..=history.len())
+            .filter_map(|i| self.table.get(&history[i..]))
+            .next()
+            .map(|x| weighted(rng, x.iter().map(|(symbol, count)| (*symbol, *count))))
+    }
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), anyhow::Error> {
+        let mut text = format!("order {}\n", self.order);
+        for (context, next) in self.table.iter() {
+            for (symbol, count) in next.iter() {
+                let context: Vec<String> = context.iter().map(format_symbol).collect();
+                text += &format!(
+                    "{} | {} {}\n",
+                    context.join(" "),
+                    format_symbol(symbol),
+                    count
+                );
+            }
+        }
+        save_file(text, path)
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        let text = std::fs::read_to_string(path)?;
+        let mut lines = text.lines();
+        let order = lines
+            .next()
+            .and_then(|x| x.strip_prefix("order "))
+            .ok_or(anyhow!("missing model order"))?
+            .parse()?;
+
+        let mut table: BTreeMap<Vec<Symbol>, BTreeMap<Symbol, u32>> = BTreeMap::new();
+        for line in lines.filter(|x| !x.trim().is_empty()) {
+            let (context, next) = line
+                .split_once('|')
+                .ok_or(anyhow!("invalid model line {}", line))?;
+            let context = context
+                .split_whitespace()
+                .map(Symbol::parse)
+                .collect::<Result<Vec<Symbol>, _>>()?;
+            let (symbol, count) = next
+                .trim()
+                .split_once(' ')
+                .ok_or(anyhow!("invalid model line {}", line))?;
+            table
+                .entry(context)
+                .or_default()
+                .insert(Symbol::parse(symbol)?, count.parse()?);
+        }
+        Ok(Self { order, table })
+    }
+}
+
+fn format_symbol(symbol: &Symbol) -> String {
+    format!("{}:{}:{}", symbol.degree, symbol.interval, symbol.duration)
+}
+
+fn to_symbols(line: &[ImportedNote]) -> Vec<Symbol> {
+    let key = estimate_key(line);
+    let mut previous = None;
+    line.iter()
+        .map(|x| {
+            let note = key.note(x.pitch.max(12), Rational::ZERO);
+            let position = note.octave as i32 * 7 + note.pitch as i32;
+            let interval = previous.map_or(0, |p| position - p);
+            previous = Some(position);
+            let sixteenths = ((x.length * 4.0).round() as i64).clamp(1, 16);
+            Symbol {
+                degree: note.pitch,
+                interval: interval.clamp(-MAX_INTERVAL, MAX_INTERVAL) as i8,
+                duration: Rational::new(sixteenths, 4),
+            }
+        })
+        .collect()
+}
+
+fn estimate_key(line: &[ImportedNote]) -> Key {
+    let mut histogram = [0.0; 12];
+    for note in line {
+        histogram[(note.pitch % 12) as usize] += note.length;
+    }
+    let fit = |key: &Key| -> f64 {
+        let mut fit = 0.0;
+        for (class, weight) in histogram.iter().enumerate() {
+            let note = key.note(class as u8 + 12, Rational::ZERO);
+            if note.accidental.is_none() {
+                fit += if note.pitch == 0 {
+                    weight * 1.5
+                } else {
+                    *weight
+                };
+            }
+        }
+        fit
+    };
+    (0..12)
+        .flat_map(|tonic| [Key::new(tonic, true), Key::new(tonic, false)])
+        .max_by(|a, b| fit(a).total_cmp(&fit(b)))
+        .unwrap()
+}
+
+pub fn generate_markov_melody<const N: usize>(
+    rng: &mut StdRng,
+    model: &MarkovModel,
+    (low, high): (i32, i32),
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    let mut history: Vec<Symbol> = Vec::new();
+    let mut position = low;
+    for bar in bars.iter_mut() {
+        let mut time = Rational::ZERO;
+        while time < bar.length() {
+            let strong = time.fract() == Rational::ZERO && time.floor() % 2 == 0;
+            let symbol = markov_symbol(rng, model, &history, bar.chord.as_ref(), strong);
+
+            let target = position + symbol.interval as i32;
+            let mut next = target - (target - symbol.degree as i32).rem_euclid(7);
+            if target - next > 3 {
+                next += 7;
+            }
+            while next < low {
+                next += 7;
+            }
+            while next - 7 >= low && next > high {
+                next -= 7;
+            }
+            position = next;
+
+            let length = symbol.duration.min(bar.length() - time);
+            let octave = (position / 7) as u8;
+            bar.add_note(
+                voice,
+                time,
+                Note::new(length, symbol.degree, octave, bar.accidental(symbol.degree)),
+            );
+            time += length;
+
+            history.push(symbol);
+            if history.len() > model.order {
+                history.remove(0);
+            }
+        }
+    }
+}
+
+fn markov_symbol(
+    rng: &mut StdRng,
+    model: &MarkovModel,
+    history: &[Symbol],
+    chord: Option<&Chord>,
+    strong: bool,
+) -> Symbol {
+    let fallback = Symbol {
+        degree: 0,
+        interval: 0,
+        duration: Rational::ONE,
+    };
+    let mut symbol = model.sample(rng, history).unwrap_or(fallback);
+    for _ in 0..3 {
+        match chord {
+            Some(chord) if strong && !chord.contains(symbol.degree) => {
+                symbol = model.sample(rng, history).unwrap_or(fallback);
+            }
+            _ => break,
+        }
+    }
+    symbol
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn round_trips_through_a_file() {
+        let symbols: Vec<Symbol> = ["0:0:1", "2:2:1/2", "4:-3:3/2"]
+            .iter()
+            .map(|x| Symbol::parse(x).unwrap())
+            .collect();
+        let mut model = MarkovModel {
+            order: 1,
+            table: BTreeMap::new(),
+        };
+        model
+            .table
+            .entry(vec![symbols[0]])
+            .or_default()
+            .insert(symbols[1], 3);
+        model
+            .table
+            .entry(Vec::new())
+            .or_default()
+            .insert(symbols[2], 1);
+
+        let path = std::env::temp_dir().join("musicgen_markov_round_trip.txt");
+        model.save(&path).unwrap();
+        let loaded = MarkovModel::load(&path).unwrap();
+        std::fs::remove_file(&path).ok();
+        assert_eq!(loaded.order, model.order);
+        assert_eq!(loaded.table, model.table);
+    }
+
+    #[test]
+    fn rejects_invalid_symbols() {
+        for text in [
+            "9:0:1", "0:0:0", "0:0:-1", "0:0:1/0", "0:0:-1/2", "0:0", "x:0:1",
+        ] {
+            assert!(Symbol::parse(text).is_err(), "{}", text);
+        }
+        let path = std::env::temp_dir().join("musicgen_markov_invalid.txt");
+        std::fs::write(&path, "order 1\n0:0:1 | 0:0:1/0 2\n").unwrap();
+        let loaded = MarkovModel::load(&path);
+        std::fs::remove_file(&path).ok();
+        assert!(loaded.is_err());
+    }
+}
@@ -0,0 +1,162 @@
+use rand::rngs::StdRng;
+
+use crate::score::*;
+
+use super::{
+    harmony::weighted,
+    nearest_degree,
+    phrase::{Cadence, Phrase},
+    rhythm::MelodyRhythm,
+};
+
+#[derive(Debug, Clone)]
+pub struct Motif {
+    notes: Vec<(Rational, Rational, i32)>,
+}
+
+impl Motif {
+    fn inverted(&self) -> Self {
+        Self {
+            notes: self.notes.iter().map(|&(s, l, x)| (s, l, -x)).collect(),
+        }
+    }
+    fn fragment(&self, length: Rational) -> Self {
+        let half = length / 2;
+        let head: Vec<(Rational, Rational, i32)> = self
+            .notes
+            .iter()
+            .filter(|x| x.0 < half)
+            .map(|&(s, l, x)| (s, l.min(half - s), x))
+            .collect();
+        let notes = head
+            .iter()
+            .copied()
+            .chain(head.iter().map(|&(s, l, x)| (s + half, l, x)))
+            .collect();
+        Self { notes }
+    }
+    fn with_rhythm(&self, rhythm: &[(Rational, Rational)]) -> Self {
+        let notes = rhythm
+            .iter()
+            .enumerate()
+            .map(|(i, &(s, l))| (s, l, self.notes[i % self.notes.len()].2))
+            .collect();
+        Self { notes }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum Development {
+    Statement,
+    Repetition,
+    Sequence(i32),
+    Inversion,
+    Fragmentation,
+    Variation,
+    Cadence(Cadence),
+}
+
+pub fn generate_motif(rng: &mut StdRng, rhythm: &MelodyRhythm, length: Rational) -> Motif {
+    let rhythm = (0..5)
+        .map(|_| rhythm.pattern(rng, length, false))
+        .max_by_key(|x| x.len())
+        .unwrap();
+    let mut step = 0;
+    let notes = rhythm
+        .into_iter()
+        .enumerate()
+        .map(|(i, (start, length))| {
+            if i > 0 {
+                step += weighted(
+                    rng,
+                    [(-2, 2), (-1, 5), (0, 1), (1, 5), (2, 2), (3, 1)].into_iter(),
+                );
+            }
+            (start, length, step)
+        })
+        .collect();
+    Motif { notes }
+}
+
+fn development_plan(rng: &mut StdRng, phrases: &[Phrase]) -> Vec<Development> {
+    let mut plan = Vec::new();
+    for phrase in phrases {
+        plan.push(Development::Statement);
+        for _ in 2..phrase.length {
+            plan.push(weighted(
+                rng,
+                [
+                    (Development::Repetition, 2),
+                    (Development::Sequence(1), 3),
+                    (Development::Sequence(-1), 3),
+                    (Development::Inversion, 2),
+                    (Development::Fragmentation, 2),
+                    (Development::Variation, 2),
+                ]
+                .into_iter(),
+            ));
+        }
+        if phrase.length > 1 {
+            plan.push(Development::Cadence(phrase.cadence));
+        }
+    }
+    plan
+}
+
+pub fn generate_motif_melody<const N: usize>(
+    rng: &mut StdRng,
+    motif: &Motif,
+    rhythm: &MelodyRhythm,
+    (low, high): (i32, i32),
+    phrases: &[Phrase],
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    let plan = development_plan(rng, phrases);
+    let mut anchor = (low + high) / 2;
+    for (bar, development) in bars.iter_mut().zip(plan) {
+        let root = bar.chord.as_ref().map_or(0, |x| x.degree as i32);
+        let statement = nearest_degree(anchor, root);
+        let (notes, start) = match development {
+            Development::Statement => (motif.clone(), statement),
+            Development::Repetition => (motif.clone(), anchor),
+            Development::Sequence(steps) => (motif.clone(), anchor + steps),
+            Development::Inversion => (motif.inverted(), anchor),
+            Development::Fragmentation => (motif.fragment(bar.length()), anchor),
+            Development::Variation => {
+                let rhythm = rhythm.pattern(rng, bar.length(), false);
+                (motif.with_rhythm(&rhythm), anchor)
+            }
+            Development::Cadence(_) => (motif.fragment(bar.length()), statement),
+        };
+
+        anchor = start;
+        let lowest = notes.notes.iter().map(|x| x.2).min().unwrap_or(0);
+        let highest = notes.notes.iter().map(|x| x.2).max().unwrap_or(0);
+        while anchor + highest > high && anchor + lowest - 7 >= low {
+            anchor -= 7;
+        }
+        while anchor + lowest < low {
+            anchor += 7;
+        }
+
+        let count = notes.notes.len();
+        for (i, &(offset, length, step)) in notes.notes.iter().enumerate() {
+            if offset >= bar.length() {
+                continue;
+            }
+            let mut position = anchor + step;
+            if let (Development::Cadence(cadence), true) = (development, i + 1 == count) {
+                position = nearest_degree(position, cadence.degrees()[0] as i32);
+            }
+            let length = length.min(bar.length() - offset);
+            let degree = position.rem_euclid(7) as u8;
+            let octave = position.div_euclid(7) as u8;
+            bar.add_note(
+                voice,
+                offset,
+                Note::new(length, degree, octave, bar.accidental(degree)),
+            );
+        }
+    }
+}
@@ -0,0 +1,141 @@
+use crate::score::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Interval {
+    Unison,
+    ThirdBelow,
+    ThirdAbove,
+}
+
+impl Interval {
+    fn steps(&self) -> i32 {
+        match self {
+            Self::Unison => 0,
+            Self::ThirdBelow => -2,
+            Self::ThirdAbove => 2,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Doubling {
+    voice: Option<usize>,
+    octaves: i32,
+    interval: Interval,
+}
+
+impl Doubling {
+    pub fn lead() -> Self {
+        Self {
+            voice: None,
+            octaves: 0,
+            interval: Interval::Unison,
+        }
+    }
+    pub fn on(voice: usize) -> Self {
+        Self {
+            voice: Some(voice),
+            ..Self::lead()
+        }
+    }
+    pub fn octaves(mut self, octaves: i32) -> Self {
+        self.octaves = octaves;
+        self
+    }
+    pub fn with_interval(mut self, interval: Interval) -> Self {
+        self.interval = interval;
+        self
+    }
+    fn steps(&self) -> i32 {
+        self.octaves * 7 + self.interval.steps()
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Orchestration {
+    doublings: Vec<Doubling>,
+    lead_rests: bool,
+}
+
+impl Orchestration {
+    pub fn new() -> Self {
+        Self::default()
+    }
+    pub fn with(mut self, doubling: Doubling) -> Self {
+        self.doublings.push(doubling);
+        self
+    }
+    pub fn resting(mut self) -> Self {
+        self.lead_rests = true;
+        self
+    }
+    pub fn voices(&self) -> Vec<usize> {
+        self.doublings.iter().filter_map(|x| x.voice).collect()
+    }
+}
+
+pub fn clear_doublings<const N: usize>(bars: &mut [Bar<N>]) {
+    for bar in bars.iter_mut() {
+        for voice in 0..N {
+            if !bar.notes[voice].iter().any(|x| x.1.doubling) {
+                continue;
+            }
+            bar.notes[voice].retain(|x| !x.1.doubling);
+            let onsets: Vec<Rational> = bar.notes[voice].iter().map(|x| x.0).collect();
+            bar.tuplets[voice].retain(|x| onsets.iter().any(|&y| x.start <= y && y < x.end()));
+        }
+    }
+}
+
+pub fn orchestrate<const N: usize>(
+    orchestration: &Orchestration,
+    lead: usize,
+    bars: &mut [Bar<N>],
+) {
+    for bar in bars.iter_mut() {
+        let melody: Vec<(Rational, Note)> = bar.notes[lead]
+            .iter()
+            .filter(|x| x.1.drum.is_none() && !x.1.doubling)
+            .cloned()
+            .collect();
+        let tuplets = bar.tuplets[lead].clone();
+
+        for doubling in orchestration.doublings.iter() {
+            let voice = doubling.voice.unwrap_or(lead);
+            let steps = doubling.steps();
+            if voice >= N || voice == lead && steps == 0 {
+                continue;
+            }
+            for (offset, note) in melody.iter() {
+                let position = note.octave as i32 * 7 + note.pitch as i32 + steps;
+                if position < 0 {
+                    continue;
+                }
+                let mut doubled = Note {
+                    pitch: position.rem_euclid(7) as u8,
+                    octave: position.div_euclid(7) as u8,
+                    doubling: true,
+                    ..note.clone()
+                };
+                if doubling.interval != Interval::Unison {
+                    doubled.accidental = bar.accidental(doubled.pitch);
+                }
+                bar.add_note(voice, *offset, doubled);
+            }
+            if voice != lead {
+                for tuplet in tuplets.iter() {
+                    if !bar.tuplets[voice].iter().any(|x| x.start == tuplet.start) {
+                        bar.add_tuplet(voice, tuplet.clone());
+                    }
+                }
+            }
+        }
+
+        if orchestration.lead_rests {
+            bar.notes[lead].retain(|x| x.1.doubling);
+            if bar.notes[lead].is_empty() {
+                bar.tuplets[lead].clear();
+            }
+        }
+    }
+}
@@ -0,0 +1,102 @@
+use crate::score::*;
+
+use super::nearest_degree;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Cadence {
+    Half,
+    Full,
+}
+
+impl Cadence {
+    pub fn degrees(&self) -> &'static [u8] {
+        match self {
+            Self::Half => &[4, 1, 6],
+            Self::Full => &[0],
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Phrase {
+    pub start: usize,
+    pub length: usize,
+    pub cadence: Cadence,
+    pub question: Option<usize>,
+}
+
+impl Phrase {
+    pub fn end(&self) -> usize {
+        self.start + self.length
+    }
+}
+
+pub fn plan_phrases(length: usize) -> Vec<Phrase> {
+    let size = if length.is_multiple_of(8) { 4 } else { 2 };
+    let mut phrases: Vec<Phrase> = Vec::new();
+    let mut start = 0;
+    while start < length {
+        let index = phrases.len();
+        let answer = index % 2 == 1;
+        let phrase_length = size.min(length - start);
+        let last = start + phrase_length == length;
+        phrases.push(Phrase {
+            start,
+            length: phrase_length,
+            cadence: if answer || last {
+                Cadence::Full
+            } else {
+                Cadence::Half
+            },
+            question: answer.then(|| index - 1),
+        });
+        start += phrase_length;
+    }
+    phrases
+}
+
+pub fn shape_phrases<const N: usize>(voice: usize, phrases: &[Phrase], bars: &mut [Bar<N>]) {
+    for phrase in phrases {
+        if let Some(question) = phrase.question {
+            let opening = bars[phrases[question].start].clone();
+            let bar = &mut bars[phrase.start];
+            if opening.length() == bar.length() {
+                let degree = |bar: &Bar<N>| bar.chord.as_ref().map_or(0, |x| x.degree as i32);
+                let shift = (degree(bar) - degree(&opening) + 3).rem_euclid(7) - 3;
+                let chord = bar.chord.clone();
+                bar.notes[voice] = opening.notes[voice].clone();
+                bar.tuplets[voice] = opening.tuplets[voice].clone();
+                for (_, note) in bar.notes[voice].iter_mut() {
+                    let position = note.octave as i32 * 7 + note.pitch as i32 + shift;
+                    note.pitch = position.rem_euclid(7) as u8;
+                    note.octave = position.div_euclid(7) as u8;
+                    note.accidental = chord.as_ref().and_then(|x| x.accidental(note.pitch));
+                    note.tie = false;
+                }
+            }
+        }
+
+        let bar = &mut bars[phrase.end() - 1];
+        let Some(last) = bar.notes[voice].iter().map(|x| x.0).max() else {
+            continue;
+        };
+        let degrees = phrase.cadence.degrees();
+        let chord = bar.chord.clone();
+        let accidental = |pitch: u8| chord.as_ref().and_then(|x| x.accidental(pitch));
+        for (_, note) in bar.notes[voice].iter_mut().filter(|x| x.0 == last) {
+            note.tie = false;
+            if degrees.contains(&note.pitch) && note.accidental == accidental(note.pitch) {
+                continue;
+            }
+            let position = note.octave as i32 * 7 + note.pitch as i32;
+            let target = degrees
+                .iter()
+                .map(|&x| nearest_degree(position, x as i32))
+                .min_by_key(|x| (x - position).abs())
+                .unwrap();
+            note.pitch = target.rem_euclid(7) as u8;
+            note.octave = target.div_euclid(7) as u8;
+            note.accidental = accidental(note.pitch);
+        }
+    }
+}
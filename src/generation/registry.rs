@@ -0,0 +1,558 @@
+use std::collections::BTreeMap;
+
+use anyhow::anyhow;
+use rand::rngs::StdRng;
+
+use crate::score::*;
+
+use super::{
+    arpeggio::generate_arpeggio,
+    bass::generate_bass,
+    contour::generate_contour_melody,
+    counter::generate_counter_melody,
+    counterpoint::enforce,
+    drums::generate_beat,
+    energy::dynamic,
+    evolve::evolve_melody,
+    fix_ties, generate_chords, generate_melody,
+    harmony::generate_progression,
+    markov::generate_markov_melody,
+    motif::generate_motif_melody,
+    orchestration::{clear_doublings, orchestrate},
+    phrase::{plan_phrases, shape_phrases, Cadence, Phrase},
+    rhythm::generate_percussion,
+    seed::Seed,
+    Melody, SectionSettings,
+};
+
+pub struct Context<'a> {
+    pub settings: &'a SectionSettings,
+    pub phrases: &'a [Phrase],
+    voices: BTreeMap<&'a str, Vec<usize>>,
+}
+
+impl Context<'_> {
+    pub fn voices(&self, generator: &str) -> &[usize] {
+        self.voices.get(generator).map_or(&[], |x| x.as_slice())
+    }
+    pub fn voice(&self, generator: &str) -> Option<usize> {
+        self.voices(generator).first().copied()
+    }
+}
+
+pub trait VoiceGenerator<const N: usize> {
+    fn name(&self) -> &str;
+    fn voices(&self) -> Vec<usize>;
+    fn dependencies(&self) -> Vec<&str> {
+        Vec::new()
+    }
+    fn after(&self) -> Vec<&str> {
+        Vec::new()
+    }
+    fn generate(&self, rng: &mut StdRng, context: &Context, bars: &mut [Bar<N>]);
+}
+
+pub struct Registry<const N: usize> {
+    generators: Vec<Box<dyn VoiceGenerator<N>>>,
+}
+
+impl<const N: usize> Default for Registry<N> {
+    fn default() -> Self {
+        Self::new()
+            .with(MelodyGenerator(0))
+            .with(ChordGenerator(1))
+            .with(DrumGenerator(3))
+            .with(BassGenerator(2))
+            .with(PercussionGenerator(4))
+            .with(ArpeggioGenerator(5))
+            .with(CounterGenerator(6))
+            .with(OrchestrationGenerator)
+    }
+}
+
+impl<const N: usize> Registry<N> {
+    pub fn new() -> Self {
+        Self {
+            generators: Vec::new(),
+        }
+    }
+    pub fn with(mut self, generator: impl VoiceGenerator<N> + 'static) -> Self {
+        self.register(Box::new(generator));
+        self
+    }
+    pub fn register(&mut self, generator: Box<dyn VoiceGenerator<N>>) {
+        self.generators.retain(|x| x.name() != generator.name());
+        self.generators.push(generator);
+    }
+    pub fn remove(&mut self, name: &str) {
+        self.generators.retain(|x| x.name() != name);
+    }
+    fn has(&self, name: &str) -> bool {
+        self.generators.iter().any(|x| x.name() == name)
+    }
+    fn order(
+        &self,
+        settings: &SectionSettings,
+    ) -> Result<Vec<&dyn VoiceGenerator<N>>, anyhow::Error> {
+        if let Some(voice) = settings
+            .orchestration
+            .voices()
+            .into_iter()
+            .find(|&x| x >= N)
+        {
+            return Err(anyhow!(
+                "orchestration doubles onto voice {} of {}",
+                voice,
+                N
+            ));
+        }
+        for generator in self.generators.iter() {
+            if let Some(voice) = generator.voices().into_iter().find(|&x| x >= N) {
+                return Err(anyhow!(
+                    "generator {} writes voice {} of {}",
+                    generator.name(),
+                    voice,
+                    N
+                ));
+            }
+            if let Some(missing) = generator.dependencies().into_iter().find(|x| !self.has(x)) {
+                return Err(anyhow!(
+                    "generator {} depends on unknown generator {}",
+                    generator.name(),
+                    missing
+                ));
+            }
+        }
+
+        let mut ordered: Vec<&dyn VoiceGenerator<N>> = Vec::new();
+        while ordered.len() < self.generators.len() {
+            let next = self.generators.iter().find(|x| {
+                !ordered.iter().any(|o| o.name() == x.name())
+                    && x.dependencies()
+                        .into_iter()
+                        .chain(x.after().into_iter().filter(|a| self.has(a)))
+                        .all(|d| ordered.iter().any(|o| o.name() == d))
+            });
+            match next {
+                Some(next) => ordered.push(next.as_ref()),
+                None => return Err(anyhow!("generator dependencies form a cycle")),
+            }
+        }
+        Ok(ordered)
+    }
+    pub fn generate(
+        &self,
+        seed: Seed,
+        settings: &SectionSettings,
+    ) -> Result<Section<N>, anyhow::Error> {
+        let order = self.order(settings)?;
+        let mut bars = vec![
+            Bar::new(
+                settings.beats,
+                settings.bpm,
+                settings.key.clone(),
+                settings.dynamic,
+            );
+            settings.length
+        ];
+        shape_energy(settings, &mut bars);
+
+        let phrases = phrases(settings, settings.length);
+        match &settings.progression {
+            Some(progression) => {
+                for (bar, chord) in bars.iter_mut().zip(progression.iter().cycle()) {
+                    bar.chord = Some(chord.clone());
+                }
+            }
+            None => {
+                let mut rng = seed.stream("harmony");
+                for phrase in phrases.iter() {
+                    let progression = generate_progression(
+                        &mut rng,
+                        &settings.harmony,
+                        phrase.length,
+                        phrase.cadence,
+                    );
+                    for (bar, chord) in bars[phrase.start..].iter_mut().zip(progression) {
+                        bar.chord = Some(chord);
+                    }
+                }
+            }
+        }
+
+        run(seed, settings, &phrases, &order, &order, &mut bars);
+        Ok(Section::from_bars(bars))
+    }
+    pub fn vary(
+        &self,
+        seed: Seed,
+        settings: &SectionSettings,
+        section: &Section<N>,
+        names: &[&str],
+    ) -> Result<Section<N>, anyhow::Error> {
+        let order = self.order(settings)?;
+        if let Some(name) = names
+            .iter()
+            .find(|x| !order.iter().any(|g| g.name() == **x))
+        {
+            return Err(anyhow!("unknown generator {}", name));
+        }
+        let mut varied: Vec<&dyn VoiceGenerator<N>> = Vec::new();
+        for generator in order.iter() {
+            let dependent = generator
+                .dependencies()
+                .into_iter()
+                .chain(generator.after())
+                .any(|d| varied.iter().any(|x| x.name() == d));
+            if dependent || names.contains(&generator.name()) || generator.name() == "orchestration"
+            {
+                varied.push(*generator);
+            }
+        }
+
+        let mut bars = section.bars().to_vec();
+        clear_doublings(&mut bars);
+        shape_energy(settings, &mut bars);
+        for bar in bars.iter_mut() {
+            for voice in varied.iter().flat_map(|x| x.voices()) {
+                bar.notes[voice].clear();
+                bar.tuplets[voice].clear();
+            }
+        }
+        let phrases = phrases(settings, bars.len());
+        run(seed, settings, &phrases, &order, &varied, &mut bars);
+        Ok(Section::from_bars(bars))
+    }
+}
+
+fn phrases(settings: &SectionSettings, length: usize) -> Vec<Phrase> {
+    let mut phrases = plan_phrases(length);
+    if let Some(progression) = &settings.progression {
+        for phrase in phrases.iter_mut() {
+            let last = &progression[(phrase.end() - 1) % progression.len()];
+            phrase.cadence = match last.function() {
+                Function::Dominant => Cadence::Half,
+                _ => Cadence::Full,
+            };
+        }
+    }
+    phrases
+}
+
+fn shape_energy<const N: usize>(settings: &SectionSettings, bars: &mut [Bar<N>]) {
+    for bar in bars.iter_mut() {
+        bar.dynamic = settings.dynamic;
+    }
+    for (bar, &level) in bars.iter_mut().zip(settings.energy.iter()) {
+        bar.energy = level;
+        bar.dynamic = dynamic(level);
+    }
+}
+
+fn run<const N: usize>(
+    seed: Seed,
+    settings: &SectionSettings,
+    phrases: &[Phrase],
+    order: &[&dyn VoiceGenerator<N>],
+    generators: &[&dyn VoiceGenerator<N>],
+    bars: &mut [Bar<N>],
+) {
+    let context = Context {
+        settings,
+        phrases,
+        voices: order.iter().map(|x| (x.name(), x.voices())).collect(),
+    };
+    for generator in generators {
+        let mut rng = seed.stream(generator.name());
+        generator.generate(&mut rng, &context, bars);
+    }
+}
+
+pub struct MelodyGenerator(pub usize);
+
+impl<const N: usize> VoiceGenerator<N> for MelodyGenerator {
+    fn name(&self) -> &str {
+        "melody"
+    }
+    fn voices(&self) -> Vec<usize> {
+        vec![self.0]
+    }
+    fn after(&self) -> Vec<&str> {
+        vec!["bass"]
+    }
+    fn generate(&self, rng: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        let settings = context.settings;
+        let voice = self.0;
+        let write = |rng: &mut StdRng, bars: &mut [Bar<N>]| match &settings.melody {
+            Melody::Shapes => generate_melody(
+                rng,
+                &settings.rhythm,
+                context.phrases,
+                voice,
+                settings.shuffle,
+                bars,
+            ),
+            Melody::Markov(model) => {
+                generate_markov_melody(rng, model, settings.range, voice, bars)
+            }
+            Melody::Contour(contour) => generate_contour_melody(
+                rng,
+                *contour,
+                &settings.rhythm,
+                settings.range,
+                context.phrases,
+                voice,
+                bars,
+            ),
+            Melody::Motif(motif) => generate_motif_melody(
+                rng,
+                motif,
+                &settings.rhythm,
+                settings.range,
+                context.phrases,
+                voice,
+                bars,
+            ),
+        };
+        match &settings.evolution {
+            Some(evolution) => evolve_melody(rng, evolution, settings.range, voice, bars, write),
+            None => write(rng, bars),
+        }
+        shape_phrases(voice, context.phrases, bars);
+        if let (Some(rules), Some(bass)) = (&settings.counterpoint, context.voice("bass")) {
+            enforce(rng, rules, voice, &[voice, bass], bars);
+        }
+        fix_ties(voice, bars);
+    }
+}
+
+pub struct ChordGenerator(pub usize);
+
+impl<const N: usize> VoiceGenerator<N> for ChordGenerator {
+    fn name(&self) -> &str {
+        "chords"
+    }
+    fn voices(&self) -> Vec<usize> {
+        vec![self.0]
+    }
+    fn generate(&self, rng: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        generate_chords(rng, &context.settings.voicing, self.0, bars);
+    }
+}
+
+pub struct DrumGenerator(pub usize);
+
+impl<const N: usize> VoiceGenerator<N> for DrumGenerator {
+    fn name(&self) -> &str {
+        "drums"
+    }
+    fn voices(&self) -> Vec<usize> {
+        vec![self.0]
+    }
+    fn generate(&self, rng: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        let settings = context.settings;
+        generate_beat(rng, settings.groove, self.0, settings.shuffle, bars);
+    }
+}
+
+pub struct BassGenerator(pub usize);
+
+impl<const N: usize> VoiceGenerator<N> for BassGenerator {
+    fn name(&self) -> &str {
+        "bass"
+    }
+    fn voices(&self) -> Vec<usize> {
+        vec![self.0]
+    }
+    fn after(&self) -> Vec<&str> {
+        vec!["drums"]
+    }
+    fn generate(&self, rng: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        let drums = context.voice("drums").unwrap_or(self.0);
+        generate_bass(rng, context.settings.bass, self.0, drums, bars);
+    }
+}
+
+pub struct PercussionGenerator(pub usize);
+
+impl<const N: usize> VoiceGenerator<N> for PercussionGenerator {
+    fn name(&self) -> &str {
+        "percussion"
+    }
+    fn voices(&self) -> Vec<usize> {
+        vec![self.0]
+    }
+    fn generate(&self, _: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        generate_percussion(&context.settings.percussion, self.0, bars);
+    }
+}
+
+pub struct ArpeggioGenerator(pub usize);
+
+impl<const N: usize> VoiceGenerator<N> for ArpeggioGenerator {
+    fn name(&self) -> &str {
+        "arpeggio"
+    }
+    fn voices(&self) -> Vec<usize> {
+        vec![self.0]
+    }
+    fn dependencies(&self) -> Vec<&str> {
+        vec!["chords"]
+    }
+    fn generate(&self, rng: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        if let (Some(arpeggio), Some(chords)) =
+            (&context.settings.arpeggio, context.voice("chords"))
+        {
+            generate_arpeggio(rng, arpeggio, chords, self.0, bars);
+        }
+    }
+}
+
+pub struct CounterGenerator(pub usize);
+
+impl<const N: usize> VoiceGenerator<N> for CounterGenerator {
+    fn name(&self) -> &str {
+        "counter"
+    }
+    fn voices(&self) -> Vec<usize> {
+        vec![self.0]
+    }
+    fn dependencies(&self) -> Vec<&str> {
+        vec!["melody"]
+    }
+    fn after(&self) -> Vec<&str> {
+        vec!["bass"]
+    }
+    fn generate(&self, rng: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        let settings = context.settings;
+        let (Some(counter), Some(melody)) = (settings.counter, context.voice("melody")) else {
+            return;
+        };
+        let (low, _) = settings.range;
+        generate_counter_melody(rng, counter, (low - 7, low + 3), melody, self.0, bars);
+        if let Some(rules) = &settings.counterpoint {
+            let voices: Vec<usize> = [melody, self.0]
+                .into_iter()
+                .chain(context.voice("bass"))
+                .collect();
+            enforce(rng, rules, self.0, &voices, bars);
+        }
+    }
+}
+
+pub struct OrchestrationGenerator;
+
+impl<const N: usize> VoiceGenerator<N> for OrchestrationGenerator {
+    fn name(&self) -> &str {
+        "orchestration"
+    }
+    fn voices(&self) -> Vec<usize> {
+        Vec::new()
+    }
+    fn dependencies(&self) -> Vec<&str> {
+        vec!["melody"]
+    }
+    fn after(&self) -> Vec<&str> {
+        vec!["chords", "arpeggio"]
+    }
+    fn generate(&self, _: &mut StdRng, context: &Context, bars: &mut [Bar<N>]) {
+        clear_doublings(bars);
+        if let Some(lead) = context.voice("melody") {
+            orchestrate(&context.settings.orchestration, lead, bars);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use super::*;
+
+    struct Stub {
+        name: &'static str,
+        dependencies: Vec<&'static str>,
+        after: Vec<&'static str>,
+    }
+
+    fn stub(name: &'static str, dependencies: &[&'static str], after: &[&'static str]) -> Stub {
+        Stub {
+            name,
+            dependencies: dependencies.to_vec(),
+            after: after.to_vec(),
+        }
+    }
+
+    impl VoiceGenerator<2> for Stub {
+        fn name(&self) -> &str {
+            self.name
+        }
+        fn voices(&self) -> Vec<usize> {
+            Vec::new()
+        }
+        fn dependencies(&self) -> Vec<&str> {
+            self.dependencies.clone()
+        }
+        fn after(&self) -> Vec<&str> {
+            self.after.clone()
+        }
+        fn generate(&self, _: &mut StdRng, _: &Context, _: &mut [Bar<2>]) {}
+    }
+
+    fn settings() -> SectionSettings {
+        SectionSettings::new(
+            4,
+            Rc::new(Key::new(0, true)),
+            120.0,
+            4,
+            Dynamic::MezzoForte,
+            false,
+        )
+    }
+
+    fn names(registry: &Registry<2>) -> Result<Vec<String>, anyhow::Error> {
+        let order = registry.order(&settings())?;
+        Ok(order.iter().map(|x| x.name().to_string()).collect())
+    }
+
+    #[test]
+    fn orders_generators_after_their_dependencies() {
+        let registry = Registry::new()
+            .with(stub("c", &["b"], &["a"]))
+            .with(stub("b", &["a"], &[]))
+            .with(stub("a", &[], &[]));
+        assert_eq!(names(&registry).unwrap(), ["a", "b", "c"]);
+    }
+
+    #[test]
+    fn rejects_missing_dependencies() {
+        let registry = Registry::new().with(stub("b", &["a"], &[]));
+        assert!(names(&registry).is_err());
+    }
+
+    #[test]
+    fn ignores_missing_order_constraints() {
+        let registry = Registry::new()
+            .with(stub("b", &[], &["a", "missing"]))
+            .with(stub("a", &[], &[]));
+        assert_eq!(names(&registry).unwrap(), ["a", "b"]);
+    }
+
+    #[test]
+    fn generates_without_a_removed_generator() {
+        let mut registry = Registry::<7>::default();
+        registry.remove("arpeggio");
+        registry.remove("bass");
+        let section = registry.generate(Seed::new(1), &settings()).unwrap();
+        assert_eq!(section.bars().len(), 4);
+        assert!(section.bars().iter().all(|x| x.notes[5].is_empty()));
+    }
+
+    #[test]
+    fn rejects_cycles() {
+        let registry = Registry::new()
+            .with(stub("a", &["b"], &[]))
+            .with(stub("b", &[], &["a"]));
+        assert!(names(&registry).is_err());
+    }
+}
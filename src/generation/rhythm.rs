@@ -0,0 +1,219 @@
+use rand::{rngs::StdRng, Rng};
+
+use crate::score::*;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Rhythm {
+    Euclidean { hits: u8, steps: u8, rotation: u8 },
+    Polyrhythm { count: u8, span: u8 },
+}
+
+impl Rhythm {
+    pub fn onsets(&self, length: Rational) -> (Vec<(Rational, Rational)>, Vec<Tuplet>) {
+        match *self {
+            Self::Euclidean {
+                hits,
+                steps,
+                rotation,
+            } => {
+                let pattern = euclidean(hits, steps, rotation);
+                let step = Rational::new(1, 4);
+                let onsets = (0..(length / step).floor())
+                    .filter(|&i| pattern[i as usize % pattern.len()])
+                    .map(|i| (step * i, step))
+                    .collect();
+                (onsets, Vec::new())
+            }
+            Self::Polyrhythm { count, span } => {
+                let span = Rational::int(span.max(1) as i64);
+                let mut onsets = Vec::new();
+                let mut tuplets = Vec::new();
+                let mut start = Rational::ZERO;
+                while start + span <= length {
+                    let tuplet = Tuplet::new(start, span, count.max(1));
+                    onsets.extend((0..tuplet.count).map(|i| (tuplet.offset(i), tuplet.unit())));
+                    if !(tuplet.unit().den() as u64).is_power_of_two() {
+                        tuplets.push(tuplet);
+                    }
+                    start += span;
+                }
+                (onsets, tuplets)
+            }
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MelodyRhythm {
+    density: f64,
+    syncopation: f64,
+    sixteenths: f64,
+    rests: f64,
+    held_endings: bool,
+}
+
+impl Default for MelodyRhythm {
+    fn default() -> Self {
+        Self::new(0.5)
+    }
+}
+
+impl MelodyRhythm {
+    pub fn new(density: f64) -> Self {
+        Self {
+            density: density.clamp(0.0, 1.0),
+            syncopation: 0.0,
+            sixteenths: 0.0,
+            rests: 0.1,
+            held_endings: true,
+        }
+    }
+    pub fn with_density(mut self, density: f64) -> Self {
+        self.density = density.clamp(0.0, 1.0);
+        self
+    }
+    pub fn syncopated(mut self, amount: f64) -> Self {
+        self.syncopation = amount.clamp(0.0, 1.0);
+        self
+    }
+    pub fn with_sixteenths(mut self, probability: f64) -> Self {
+        self.sixteenths = probability.clamp(0.0, 1.0);
+        self
+    }
+    pub fn with_rests(mut self, probability: f64) -> Self {
+        self.rests = probability.clamp(0.0, 1.0);
+        self
+    }
+    pub fn held_endings(mut self, held: bool) -> Self {
+        self.held_endings = held;
+        self
+    }
+    pub fn density(&self) -> f64 {
+        self.density
+    }
+    pub fn pattern(
+        &self,
+        rng: &mut StdRng,
+        length: Rational,
+        ending: bool,
+    ) -> Vec<(Rational, Rational)> {
+        let half = Rational::new(1, 2);
+        let mut onsets = Vec::new();
+        for i in 0..(length / half).floor() {
+            let start = half * i;
+            let chance = match i {
+                0 => 1.0,
+                _ if i % 2 == 0 => 0.25 + 0.75 * self.density,
+                _ => 0.8 * self.density * self.density,
+            };
+            if !rng.gen_bool(chance) {
+                continue;
+            }
+            if i > 0 && i % 2 == 0 && rng.gen_bool(self.syncopation) {
+                onsets.push(start - half);
+            } else {
+                onsets.push(start);
+            }
+        }
+        onsets.sort();
+        onsets.dedup();
+
+        let mut notes: Vec<(Rational, Rational)> = Vec::new();
+        for (i, &start) in onsets.iter().enumerate() {
+            let end = onsets.get(i + 1).copied().unwrap_or(length);
+            if i == 0 || !rng.gen_bool(self.rests) {
+                notes.push((start, end - start));
+            }
+        }
+
+        if ending && self.held_endings {
+            let longest = |k: usize| {
+                notes[..k]
+                    .iter()
+                    .map(|x| x.1)
+                    .max()
+                    .unwrap_or(Rational::ZERO)
+            };
+            let last = (0..notes.len())
+                .rev()
+                .find(|&k| length - notes[k].0 >= longest(k))
+                .unwrap_or(0);
+            notes.truncate(last + 1);
+            notes[last].1 = length - notes[last].0;
+        }
+
+        let quarter = Rational::new(1, 4);
+        let count = notes.len();
+        let mut pattern = Vec::new();
+        for (i, (start, length)) in notes.into_iter().enumerate() {
+            let held = ending && self.held_endings && i + 1 == count;
+            if !held && length >= half && rng.gen_bool(self.sixteenths) {
+                pattern.push((start, quarter));
+                pattern.push((start + quarter, length - quarter));
+            } else {
+                pattern.push((start, length));
+            }
+        }
+        pattern
+    }
+}
+
+pub fn euclidean(hits: u8, steps: u8, rotation: u8) -> Vec<bool> {
+    let steps = steps.max(1) as usize;
+    let hits = (hits as usize).min(steps);
+    (0..steps)
+        .map(|i| (i + steps - rotation as usize % steps) % steps)
+        .map(|i| (i * hits) % steps < hits)
+        .collect()
+}
+
+pub fn generate_percussion<const N: usize>(
+    layers: &[(Drum, Rhythm)],
+    voice: usize,
+    bars: &mut [Bar<N>],
+) {
+    for bar in bars.iter_mut() {
+        for (drum, rhythm) in layers {
+            let (onsets, tuplets) = rhythm.onsets(bar.length());
+            for (offset, length) in onsets {
+                bar.add_note(voice, offset, Note::hit(length, *drum));
+            }
+            for tuplet in tuplets {
+                bar.add_tuplet(voice, tuplet);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn pattern(hits: u8, steps: u8, rotation: u8) -> String {
+        euclidean(hits, steps, rotation)
+            .into_iter()
+            .map(|x| if x { 'x' } else { '.' })
+            .collect()
+    }
+
+    #[test]
+    fn spreads_hits_evenly() {
+        assert_eq!(pattern(3, 8, 0), "x..x..x.");
+        assert_eq!(pattern(5, 8, 0), "x.x.xx.x");
+        assert_eq!(pattern(4, 16, 0), "x...x...x...x...");
+    }
+
+    #[test]
+    fn rotates_to_the_right() {
+        assert_eq!(pattern(3, 8, 1), ".x..x..x");
+        assert_eq!(pattern(3, 8, 9), pattern(3, 8, 1));
+    }
+
+    #[test]
+    fn clamps_hits_and_steps() {
+        assert_eq!(pattern(0, 8, 0), "........");
+        assert_eq!(pattern(8, 8, 0), "xxxxxxxx");
+        assert_eq!(pattern(12, 8, 3), "xxxxxxxx");
+        assert_eq!(pattern(1, 0, 0), "x");
+    }
+}
@@ -0,0 +1,155 @@
+use crate::score::*;
+
+const LEADING_TONE: u8 = 6;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum VoicingStyle {
+    Closed,
+    Open,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Voicing {
+    style: VoicingStyle,
+    range: (i32, i32),
+}
+
+impl Voicing {
+    pub fn new(style: VoicingStyle) -> Self {
+        Self {
+            style,
+            range: (28, 40),
+        }
+    }
+    pub fn within(mut self, (low_octave, low): (u8, u8), (high_octave, high): (u8, u8)) -> Self {
+        self.range = (
+            low_octave as i32 * 7 + low as i32,
+            high_octave as i32 * 7 + high as i32,
+        );
+        self
+    }
+    pub fn voice(&self, chords: &[Chord]) -> Vec<Vec<i32>> {
+        let mut previous: Option<Vec<i32>> = None;
+        chords
+            .iter()
+            .map(|chord| {
+                let voicing = self
+                    .candidates(chord)
+                    .into_iter()
+                    .min_by_key(|x| self.cost(x, previous.as_deref()))
+                    .unwrap();
+                previous = Some(voicing.clone());
+                voicing
+            })
+            .collect()
+    }
+    fn candidates(&self, chord: &Chord) -> Vec<Vec<i32>> {
+        let mut tones = chord.tones();
+        if tones.len() == 3 {
+            let doubled = if tones[0] == LEADING_TONE {
+                tones[1]
+            } else {
+                tones[0]
+            };
+            tones.push(doubled);
+        }
+
+        let (low, high) = (self.range.0 - 7, self.range.1 + 7);
+        let mut candidates: Vec<Vec<i32>> = vec![Vec::new()];
+        for tone in tones {
+            let positions: Vec<i32> = (low..=high)
+                .filter(|x| x.rem_euclid(7) == tone as i32)
+                .collect();
+            candidates = candidates
+                .into_iter()
+                .flat_map(|x| {
+                    positions
+                        .iter()
+                        .filter(|p| !x.contains(p))
+                        .map(|&p| [x.as_slice(), &[p]].concat())
+                        .collect::<Vec<_>>()
+                })
+                .collect();
+        }
+        for candidate in candidates.iter_mut() {
+            candidate.sort();
+        }
+        candidates.sort();
+        candidates.dedup();
+        candidates.retain(|x| self.fits(x));
+        candidates
+    }
+    fn fits(&self, voicing: &[i32]) -> bool {
+        let upper = &voicing[1..];
+        let span = upper[upper.len() - 1] - upper[0];
+        let gaps = upper.windows(2).all(|x| x[1] - x[0] <= 7);
+        let bass = upper[0] - voicing[0] <= 11;
+        let style = match self.style {
+            VoicingStyle::Closed => span <= 7,
+            VoicingStyle::Open => span > 7 && span <= 14,
+        };
+        gaps && bass && style
+    }
+    fn cost(&self, voicing: &[i32], previous: Option<&[i32]>) -> i32 {
+        let outside: i32 = voicing
+            .iter()
+            .map(|&x| (self.range.0 - x).max(0) + (x - self.range.1).max(0))
+            .sum();
+        let top = voicing[voicing.len() - 1];
+        let movement = match previous {
+            Some(previous) => {
+                let total: i32 = voicing
+                    .iter()
+                    .zip(previous.iter())
+                    .map(|(a, b)| (a - b).abs())
+                    .sum();
+                let leap = (top - previous[previous.len() - 1]).abs();
+                total + 2 * leap + if leap > 2 { 4 } else { 0 }
+            }
+            None => (top - self.range.1 + 2).abs(),
+        };
+        10 * outside + movement
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn progression() -> Vec<Chord> {
+        [0, 5, 3, 4, 0]
+            .into_iter()
+            .map(|x| Chord::new(x, false))
+            .collect()
+    }
+
+    fn span(voicing: &[i32]) -> i32 {
+        voicing[voicing.len() - 1] - voicing[1]
+    }
+
+    #[test]
+    fn closed_and_open_spread_the_upper_voices() {
+        for voicing in Voicing::new(VoicingStyle::Closed).voice(&progression()) {
+            assert!(span(&voicing) <= 7, "{:?}", voicing);
+        }
+        for voicing in Voicing::new(VoicingStyle::Open).voice(&progression()) {
+            assert!((8..=14).contains(&span(&voicing)), "{:?}", voicing);
+        }
+    }
+
+    #[test]
+    fn holds_a_common_tone() {
+        let voicings = Voicing::new(VoicingStyle::Closed).voice(&progression());
+        for (pair, chords) in voicings.windows(2).zip(progression().windows(2)) {
+            if !chords[0].tones().iter().any(|x| chords[1].contains(*x)) {
+                continue;
+            }
+            assert!(
+                pair[0].iter().any(|x| pair[1].contains(x)),
+                "{:?} -> {:?} holds no common tone",
+                pair[0],
+                pair[1]
+            );
+        }
+    }
+}
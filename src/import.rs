@@ -0,0 +1,62 @@
+pub mod abc;
+pub mod midi;
+
+use std::path::Path;
+
+#[derive(Debug, Clone)]
+pub struct ImportedNote {
+    pub start: f64,
+    pub length: f64,
+    pub pitch: u8,
+}
+
+pub fn import_file<P>(path: P) -> Result<Vec<Vec<ImportedNote>>, anyhow::Error>
+where
+    P: AsRef<Path>,
+{
+    let path = path.as_ref();
+    let extension = path
+        .extension()
+        .and_then(|x| x.to_str())
+        .map(|x| x.to_lowercase());
+    match extension.as_deref() {
+        Some("mid") | Some("midi") => midi::read_midi(&std::fs::read(path)?),
+        Some("abc") => Ok(abc::read_abc(&std::fs::read_to_string(path)?)),
+        _ => Err(anyhow::anyhow!("unsupported file {}", path.display())),
+    }
+}
+
+pub fn import_folder<P>(path: P) -> Result<Vec<Vec<ImportedNote>>, anyhow::Error>
+where
+    P: AsRef<Path>,
+{
+    let mut lines = Vec::new();
+    for entry in std::fs::read_dir(path)? {
+        let path = entry?.path();
+        if path.is_dir() {
+            lines.extend(import_folder(&path)?);
+        } else {
+            match import_file(&path) {
+                Ok(imported) => lines.extend(imported),
+                Err(e) => eprintln!("Skipping {}: {}", path.display(), e),
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn skyline(mut notes: Vec<ImportedNote>) -> Vec<ImportedNote> {
+    notes.sort_by(|a, b| a.start.total_cmp(&b.start).then(b.pitch.cmp(&a.pitch)));
+    let mut line: Vec<ImportedNote> = Vec::new();
+    for note in notes {
+        match line.last_mut() {
+            Some(last) if (last.start - note.start).abs() < 1e-6 => (),
+            Some(last) => {
+                last.length = last.length.min(note.start - last.start);
+                line.push(note);
+            }
+            None => line.push(note),
+        }
+    }
+    line
+}
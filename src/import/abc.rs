@@ -0,0 +1,345 @@
+use std::collections::HashMap;
+
+use super::{skyline, ImportedNote};
+
+const LETTERS: [(char, u8, i32); 7] = [
+    ('C', 0, 0),
+    ('D', 2, 2),
+    ('E', 4, 4),
+    ('F', 5, -1),
+    ('G', 7, 1),
+    ('A', 9, 3),
+    ('B', 11, 5),
+];
+const SHARP_ORDER: [char; 7] = ['F', 'C', 'G', 'D', 'A', 'E', 'B'];
+
+#[derive(Debug, Default)]
+struct Voice {
+    time: f64,
+    notes: Vec<ImportedNote>,
+    tied: bool,
+    percussion: bool,
+}
+
+struct Tune {
+    unit: f64,
+    signature: HashMap<char, i32>,
+    accidentals: HashMap<(char, i32), i32>,
+    voices: HashMap<String, Voice>,
+    voice: String,
+    tuplet: Option<(f64, usize)>,
+    broken: f64,
+}
+
+impl Tune {
+    fn new() -> Self {
+        Self {
+            unit: 0.5,
+            signature: HashMap::new(),
+            accidentals: HashMap::new(),
+            voices: HashMap::new(),
+            voice: "1".to_string(),
+            tuplet: None,
+            broken: 1.0,
+        }
+    }
+    fn field(&mut self, name: char, value: &str) {
+        let value = value.trim();
+        match name {
+            'L' => {
+                if let Some(unit) = parse_fraction(value) {
+                    self.unit = unit * 4.0;
+                }
+            }
+            'K' => self.signature = key_signature(value),
+            'V' => {
+                self.voice = value.split_whitespace().next().unwrap_or("1").to_string();
+                if value.contains("clef=perc") {
+                    self.voice().percussion = true;
+                }
+            }
+            _ => (),
+        }
+    }
+    fn voice(&mut self) -> &mut Voice {
+        self.voices.entry(self.voice.clone()).or_default()
+    }
+    fn scale(&mut self) -> f64 {
+        let mut scale = self.broken;
+        self.broken = 1.0;
+        if let Some((factor, remaining)) = self.tuplet {
+            scale *= factor;
+            self.tuplet = (remaining > 1).then_some((factor, remaining - 1));
+        }
+        scale
+    }
+    fn push(&mut self, pitch: Option<u8>, length: f64) {
+        let length = length * self.scale();
+        let voice = self.voice();
+        let tied = std::mem::take(&mut voice.tied);
+        if let Some(pitch) = pitch {
+            match voice.notes.last_mut() {
+                Some(last) if tied && last.pitch == pitch => last.length += length,
+                _ => voice.notes.push(ImportedNote {
+                    start: voice.time,
+                    length,
+                    pitch,
+                }),
+            }
+        }
+        voice.time += length;
+    }
+    fn broken_rhythm(&mut self, longer_first: bool) {
+        let (first, second) = if longer_first { (1.5, 0.5) } else { (0.5, 1.5) };
+        let voice = self.voice();
+        if let Some(last) = voice.notes.last_mut() {
+            voice.time += last.length * (first - 1.0);
+            last.length *= first;
+        }
+        self.broken = second;
+    }
+    fn finish(self) -> Vec<Vec<ImportedNote>> {
+        self.voices
+            .into_values()
+            .filter(|x| !x.percussion)
+            .map(|x| skyline(x.notes))
+            .filter(|x| !x.is_empty())
+            .collect()
+    }
+}
+
+pub fn read_abc(text: &str) -> Vec<Vec<ImportedNote>> {
+    let mut lines = Vec::new();
+    let mut tune = Tune::new();
+    for line in text.lines() {
+        let line = line.split('%').next().unwrap_or("");
+        let chars: Vec<char> = line.chars().collect();
+        if chars.len() >= 2 && chars[0].is_ascii_alphabetic() && chars[1] == ':' {
+            if chars[0] == 'X' {
+                lines.extend(std::mem::replace(&mut tune, Tune::new()).finish());
+            }
+            tune.field(chars[0], &line[2..]);
+        } else {
+            read_body(&mut tune, &chars);
+        }
+    }
+    lines.extend(tune.finish());
+    lines
+}
+
+fn read_body(tune: &mut Tune, chars: &[char]) {
+    let mut i = 0;
+    while i < chars.len() {
+        let c = chars[i];
+        match c {
+            '"' | '!' | '+' => i = skip_until(chars, i + 1, c),
+            '{' => i = skip_until(chars, i + 1, '}'),
+            '[' if chars.get(i + 2) == Some(&':') => {
+                let end = skip_until(chars, i + 1, ']');
+                let value: String = chars[i + 3..end.saturating_sub(1).max(i + 3)]
+                    .iter()
+                    .collect();
+                tune.field(chars[i + 1], &value);
+                i = end;
+            }
+            '[' if chars.get(i + 1).is_some_and(|x| x.is_ascii_digit()) => i += 2,
+            '[' => {
+                let end = skip_until(chars, i + 1, ']');
+                let mut highest = None;
+                let mut length = None;
+                let mut j = i + 1;
+                while j < end - 1 {
+                    match read_note(tune, chars, j) {
+                        Some((pitch, note_length, next)) => {
+                            highest = highest.max(Some(pitch));
+                            length = length.or(Some(note_length));
+                            j = next;
+                        }
+                        None => j += 1,
+                    }
+                }
+                let (multiplier, next) = read_length(chars, end);
+                tune.push(highest, length.unwrap_or(tune.unit) * multiplier);
+                i = next;
+            }
+            '|' | ':' | ']' => {
+                tune.accidentals.clear();
+                i += 1;
+                while chars
+                    .get(i)
+                    .is_some_and(|x| x.is_ascii_digit() || *x == ',')
+                {
+                    i += 1;
+                }
+            }
+            '(' if chars.get(i + 1).is_some_and(|x| x.is_ascii_digit()) => {
+                let (numbers, next) = read_tuplet(chars, i + 1);
+                let p = numbers[0];
+                let q = numbers
+                    .get(1)
+                    .copied()
+                    .unwrap_or(if p % 2 == 0 && p != 6 { 3 } else { 2 });
+                let r = numbers.get(2).copied().unwrap_or(p);
+                tune.tuplet = Some((q as f64 / p as f64, r));
+                i = next;
+            }
+            '-' => {
+                tune.voice().tied = true;
+                i += 1;
+            }
+            '>' | '<' => {
+                tune.broken_rhythm(c == '>');
+                i += 1;
+            }
+            'z' | 'x' => {
+                let (multiplier, next) = read_length(chars, i + 1);
+                tune.push(None, tune.unit * multiplier);
+                i = next;
+            }
+            _ => match read_note(tune, chars, i) {
+                Some((pitch, length, next)) => {
+                    tune.push(Some(pitch), length);
+                    i = next;
+                }
+                None => i += 1,
+            },
+        }
+    }
+}
+
+fn read_note(tune: &mut Tune, chars: &[char], start: usize) -> Option<(u8, f64, usize)> {
+    let mut i = start;
+    let mut accidental = None;
+    while let Some(&c) = chars.get(i).filter(|x| matches!(x, '^' | '_' | '=')) {
+        let step = match c {
+            '^' => 1,
+            '_' => -1,
+            _ => 0,
+        };
+        accidental = Some(accidental.unwrap_or(0) + step);
+        i += 1;
+    }
+
+    let letter = *chars.get(i)?;
+    let &(name, class, _) = LETTERS
+        .iter()
+        .find(|x| x.0 == letter.to_ascii_uppercase())?;
+    let mut octave = if letter.is_ascii_lowercase() { 1 } else { 0 };
+    i += 1;
+    while let Some(&c) = chars.get(i).filter(|x| matches!(x, '\'' | ',')) {
+        octave += if c == '\'' { 1 } else { -1 };
+        i += 1;
+    }
+
+    let shift = match accidental {
+        Some(shift) => {
+            tune.accidentals.insert((name, octave), shift);
+            shift
+        }
+        None => tune
+            .accidentals
+            .get(&(name, octave))
+            .or(tune.signature.get(&name))
+            .copied()
+            .unwrap_or(0),
+    };
+    let pitch = (60 + octave * 12 + class as i32 + shift).clamp(0, 127) as u8;
+    let (multiplier, next) = read_length(chars, i);
+    Some((pitch, tune.unit * multiplier, next))
+}
+
+fn read_length(chars: &[char], start: usize) -> (f64, usize) {
+    let mut i = start;
+    let (numerator, next) = read_number(chars, i);
+    i = next;
+    let mut length = numerator.unwrap_or(1) as f64;
+    while chars.get(i) == Some(&'/') {
+        let (denominator, next) = read_number(chars, i + 1);
+        length /= denominator.unwrap_or(2) as f64;
+        i = next;
+    }
+    (length, i)
+}
+
+fn read_number(chars: &[char], start: usize) -> (Option<usize>, usize) {
+    let end = start
+        + chars[start.min(chars.len())..]
+            .iter()
+            .take_while(|x| x.is_ascii_digit())
+            .count();
+    let number: String = chars[start.min(chars.len())..end].iter().collect();
+    (number.parse().ok(), end)
+}
+
+fn read_tuplet(chars: &[char], start: usize) -> (Vec<usize>, usize) {
+    let mut numbers = Vec::new();
+    let mut i = start;
+    loop {
+        let (number, next) = read_number(chars, i);
+        numbers.push(number.unwrap_or(0));
+        i = next;
+        if chars.get(i) != Some(&':') {
+            break;
+        }
+        i += 1;
+    }
+    let numbers: Vec<usize> = numbers.into_iter().filter(|x| *x > 0).collect();
+    if numbers.is_empty() {
+        return (vec![3], i);
+    }
+    (numbers, i)
+}
+
+fn skip_until(chars: &[char], start: usize, end: char) -> usize {
+    chars[start.min(chars.len())..]
+        .iter()
+        .position(|x| *x == end)
+        .map_or(chars.len(), |x| start + x + 1)
+}
+
+fn parse_fraction(value: &str) -> Option<f64> {
+    let (numerator, denominator) = value.split_once('/')?;
+    let numerator: f64 = numerator.trim().parse().ok()?;
+    let denominator: f64 = denominator.trim().parse().ok()?;
+    Some(numerator / denominator)
+}
+
+fn key_signature(value: &str) -> HashMap<char, i32> {
+    let mut chars = value.chars();
+    let Some(&(_, _, fifths)) = chars
+        .next()
+        .and_then(|x| LETTERS.iter().find(|y| y.0 == x.to_ascii_uppercase()))
+    else {
+        return HashMap::new();
+    };
+    let rest: String = chars.collect::<String>().to_lowercase();
+    let (fifths, mode) = match rest.chars().next() {
+        Some('#') => (fifths + 7, &rest[1..]),
+        Some('b') => (fifths - 7, &rest[1..]),
+        _ => (fifths, rest.as_str()),
+    };
+    let mode = mode.split_whitespace().next().unwrap_or("");
+    let offset = match mode.get(..3).unwrap_or(mode) {
+        "m" | "min" | "aeo" => -3,
+        "mix" => -1,
+        "dor" => -2,
+        "phr" => -4,
+        "lyd" => 1,
+        "loc" => -5,
+        _ => 0,
+    };
+
+    let fifths = fifths + offset;
+    let count = fifths.unsigned_abs() as usize;
+    let letters = SHARP_ORDER.iter().take(count.min(7));
+    if fifths >= 0 {
+        letters.map(|x| (*x, 1)).collect()
+    } else {
+        SHARP_ORDER
+            .iter()
+            .rev()
+            .take(count.min(7))
+            .map(|x| (*x, -1))
+            .collect()
+    }
+}
@@ -0,0 +1,150 @@
+use std::collections::HashMap;
+
+use anyhow::anyhow;
+
+use super::{skyline, ImportedNote};
+
+const DRUM_CHANNEL: u8 = 9;
+
+struct Reader<'a> {
+    data: &'a [u8],
+    position: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn new(data: &'a [u8]) -> Self {
+        Self { data, position: 0 }
+    }
+    fn done(&self) -> bool {
+        self.position >= self.data.len()
+    }
+    fn bytes(&mut self, count: usize) -> Result<&'a [u8], anyhow::Error> {
+        let end = self.position + count;
+        let bytes = self
+            .data
+            .get(self.position..end)
+            .ok_or(anyhow!("unexpected end of midi data"))?;
+        self.position = end;
+        Ok(bytes)
+    }
+    fn byte(&mut self) -> Result<u8, anyhow::Error> {
+        Ok(self.bytes(1)?[0])
+    }
+    fn peek(&self) -> Result<u8, anyhow::Error> {
+        self.data
+            .get(self.position)
+            .copied()
+            .ok_or(anyhow!("unexpected end of midi data"))
+    }
+    fn u16(&mut self) -> Result<u16, anyhow::Error> {
+        Ok(u16::from_be_bytes(self.bytes(2)?.try_into()?))
+    }
+    fn u32(&mut self) -> Result<u32, anyhow::Error> {
+        Ok(u32::from_be_bytes(self.bytes(4)?.try_into()?))
+    }
+    fn variable(&mut self) -> Result<u32, anyhow::Error> {
+        let mut value = 0;
+        loop {
+            let byte = self.byte()?;
+            value = (value << 7) | (byte & 0x7F) as u32;
+            if byte & 0x80 == 0 {
+                return Ok(value);
+            }
+        }
+    }
+}
+
+pub fn read_midi(data: &[u8]) -> Result<Vec<Vec<ImportedNote>>, anyhow::Error> {
+    let mut reader = Reader::new(data);
+    if reader.bytes(4)? != b"MThd" {
+        return Err(anyhow!("not a midi file"));
+    }
+    let header_length = reader.u32()? as usize;
+    let header = reader.bytes(header_length)?;
+    let mut header = Reader::new(header);
+    let _format = header.u16()?;
+    let track_count = header.u16()?;
+    let division = header.u16()?;
+    if division & 0x8000 != 0 {
+        return Err(anyhow!("smpte time division is not supported"));
+    }
+    let ticks_per_beat = division as f64;
+
+    let mut lines = Vec::new();
+    for _ in 0..track_count {
+        if reader.done() {
+            break;
+        }
+        let tag = reader.bytes(4)?;
+        let length = reader.u32()? as usize;
+        let chunk = reader.bytes(length)?;
+        if tag != b"MTrk" {
+            continue;
+        }
+        for notes in read_track(chunk, ticks_per_beat)?.into_values() {
+            let line = skyline(notes);
+            if !line.is_empty() {
+                lines.push(line);
+            }
+        }
+    }
+    Ok(lines)
+}
+
+fn read_track(
+    data: &[u8],
+    ticks_per_beat: f64,
+) -> Result<HashMap<u8, Vec<ImportedNote>>, anyhow::Error> {
+    let mut reader = Reader::new(data);
+    let mut channels: HashMap<u8, Vec<ImportedNote>> = HashMap::new();
+    let mut pending: HashMap<(u8, u8), u64> = HashMap::new();
+    let mut tick: u64 = 0;
+    let mut status = 0;
+
+    while !reader.done() {
+        tick += reader.variable()? as u64;
+        if reader.peek()? & 0x80 != 0 {
+            status = reader.byte()?;
+        }
+        match status {
+            0xFF => {
+                let _kind = reader.byte()?;
+                let length = reader.variable()? as usize;
+                reader.bytes(length)?;
+            }
+            0xF0 | 0xF7 => {
+                let length = reader.variable()? as usize;
+                reader.bytes(length)?;
+            }
+            _ => {
+                let channel = status & 0x0F;
+                match status & 0xF0 {
+                    0x80 | 0x90 => {
+                        let pitch = reader.byte()?;
+                        let velocity = reader.byte()?;
+                        if let Some(start) = pending.remove(&(channel, pitch)) {
+                            if channel != DRUM_CHANNEL {
+                                channels.entry(channel).or_default().push(ImportedNote {
+                                    start: start as f64 / ticks_per_beat,
+                                    length: (tick - start) as f64 / ticks_per_beat,
+                                    pitch,
+                                });
+                            }
+                        }
+                        if status & 0xF0 == 0x90 && velocity > 0 {
+                            pending.insert((channel, pitch), tick);
+                        }
+                    }
+                    0xC0 | 0xD0 => {
+                        reader.byte()?;
+                    }
+                    0xA0 | 0xB0 | 0xE0 => {
+                        reader.bytes(2)?;
+                    }
+                    _ => return Err(anyhow!("invalid midi status byte {:#x}", status)),
+                }
+            }
+        }
+    }
+    Ok(channels)
+}
@@ -0,0 +1,6 @@
+pub mod export;
+pub mod generation;
+pub mod import;
+pub mod playback;
+pub mod score;
+pub mod style;
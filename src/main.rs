@@ -1,20 +1,41 @@
-mod generation;
-mod playback;
-mod score;
-
 use std::rc::Rc;
+use std::time::Duration;
+
+use anyhow::anyhow;
 
-use fundsp::hacker::*;
-use rand::{rngs::StdRng, thread_rng, Rng, SeedableRng};
-use score::{Dynamic, Key, Score};
+use rand::{thread_rng, Rng};
 
-use crate::{
-    generation::{generate_section, SectionSettings},
+use musicgen::{
+    export::{abc::save_abc, midi::save_midi},
+    generation::{
+        arpeggio::{ArpPattern, Arpeggio, Rate},
+        bass::BassStyle,
+        chart::{fit_key, Chart},
+        contour::Contour,
+        counter::CounterStyle,
+        counterpoint::{check, RuleSet},
+        drums::Groove,
+        energy::{generate_curve, EnergyCurve},
+        evolve::{Evolution, Fitness},
+        form::{generate_form, Form, FormKind, FormSection, Role},
+        harmony::Harmony,
+        markov::MarkovModel,
+        motif::{generate_motif, Motif},
+        orchestration::{Doubling, Interval, Orchestration},
+        registry::Registry,
+        rhythm::{MelodyRhythm, Rhythm},
+        seed::Seed,
+        voicing::{Voicing, VoicingStyle},
+        Melody, SectionSettings,
+    },
+    import::import_folder,
     playback::{
-        instrument::{Instrument, SoundMix},
+        instrument::{DrumKit, Instrument, SoundMix},
         playback, save,
         synth::*,
     },
+    score::{Drum, Key, Rational, Score, Section},
+    style::Style,
 };
 
 fn main() {
@@ -25,56 +46,377 @@ fn main() {
 }
 
 fn run() -> Result<(), anyhow::Error> {
-    let mut seed_rng = thread_rng();
-    let seed: u32 = seed_rng.gen();
-    println!("Seed: {}", seed);
-    let mut rng = StdRng::seed_from_u64(seed as u64);
-    let key = Rc::new(Key::new(rng.gen_range(-0..=4), rng.gen_bool(0.5)));
-    let bpm = rng.gen_range(90..=130) as f64;
+    let args: Vec<String> = std::env::args().skip(1).collect();
+    if args.first().map(|x| x.as_str()) == Some("train") {
+        return train(&args[1..]);
+    }
+    let model = match args.iter().position(|x| x == "--model") {
+        Some(i) => {
+            let path = args.get(i + 1).ok_or(anyhow!("--model needs a file"))?;
+            Some(Rc::new(MarkovModel::load(path)?))
+        }
+        None => None,
+    };
+    let style = match args.iter().position(|x| x == "--style") {
+        Some(i) => {
+            let name = args
+                .get(i + 1)
+                .ok_or(anyhow!("--style needs a name or file"))?;
+            Some(Style::find(name)?)
+        }
+        None => None,
+    };
+    let chart = match args.iter().position(|x| x == "--chords") {
+        Some(i) => {
+            let chart = args
+                .get(i + 1)
+                .ok_or(anyhow!("--chords needs a chord chart"))?;
+            Some(Chart::parse(chart)?)
+        }
+        None => None,
+    };
+    let evolution = match args.iter().position(|x| x == "--evolve") {
+        Some(i) => {
+            let generations = match args.get(i + 1).filter(|x| !x.starts_with("--")) {
+                Some(x) => x
+                    .parse()
+                    .map_err(|_| anyhow!("--evolve needs a number of generations, not {}", x))?,
+                None => 40,
+            };
+            let mut evolution = Evolution::new(generations);
+            if let Some(i) = args.iter().position(|x| x == "--evolve-population") {
+                let population: usize = args
+                    .get(i + 1)
+                    .ok_or(anyhow!("--evolve-population needs a number of melodies"))?
+                    .parse()?;
+                evolution = evolution.with_population(population);
+            }
+            if let Some(i) = args.iter().position(|x| x == "--evolve-weights") {
+                let weights = args
+                    .get(i + 1)
+                    .ok_or(anyhow!("--evolve-weights needs five weights"))?
+                    .split_whitespace()
+                    .map(|x| match x.parse::<f64>() {
+                        Ok(x) if x.is_finite() && x >= 0.0 => Ok(x),
+                        _ => Err(anyhow!("invalid fitness weight {}", x)),
+                    })
+                    .collect::<Result<Vec<_>, _>>()?;
+                let [chord_tones, smoothness, repetition, range, variety] = weights[..] else {
+                    return Err(anyhow!(
+                        "--evolve-weights needs five weights, not {}",
+                        weights.len()
+                    ));
+                };
+                evolution = evolution.with_fitness(Fitness::new(
+                    chord_tones,
+                    smoothness,
+                    repetition,
+                    range,
+                    variety,
+                ));
+            }
+            if let Some(i) = args.iter().position(|x| x == "--evolve-time") {
+                let seconds: f64 = args
+                    .get(i + 1)
+                    .ok_or(anyhow!("--evolve-time needs a number of seconds"))?
+                    .parse()?;
+                println!("--evolve-time depends on the speed of the machine, so the seed no longer fixes the song");
+                evolution = evolution.with_time_limit(Duration::try_from_secs_f64(seconds)?);
+            }
+            Some(evolution)
+        }
+        None => None,
+    };
+    let curve = match args.iter().position(|x| x == "--energy") {
+        Some(i) => {
+            let curve = args
+                .get(i + 1)
+                .ok_or(anyhow!("--energy needs a list of levels"))?;
+            Some(EnergyCurve::parse(curve)?)
+        }
+        None => None,
+    };
+    let form = match args.iter().position(|x| x == "--form") {
+        Some(i) => Some(
+            args.get(i + 1)
+                .ok_or(anyhow!("--form needs a name or file"))?
+                .clone(),
+        ),
+        None => None,
+    };
 
-    let intro = generate_section(
-        &mut rng,
-        SectionSettings::new(4, key.clone(), bpm, 4, Dynamic::MezzoForte),
-    );
+    let seed: u32 = match args.iter().position(|x| x == "--seed") {
+        Some(i) => args
+            .get(i + 1)
+            .ok_or(anyhow!("--seed needs a number"))?
+            .parse()?,
+        None => thread_rng().gen(),
+    };
+    let rerolls: Vec<&str> = args
+        .windows(2)
+        .filter(|x| x[0] == "--reroll")
+        .map(|x| x[1].as_str())
+        .collect();
 
-    let sec_a = generate_section(
-        &mut rng,
-        SectionSettings::new(8, key.clone(), bpm, 4, Dynamic::Forte),
+    println!("Seed: {}", seed);
+    let master = Seed::new(seed as u64);
+    let style = style.unwrap_or_else(|| Style::random(&mut master.stream("style")));
+    println!("Style: {}", style.name);
+    let mut form = match form {
+        Some(name) => Form::find(&mut master.stream("form"), &name)?,
+        None => {
+            let mut rng = master.stream("form");
+            let kind = FormKind::random(&mut rng);
+            generate_form(&mut rng, kind)
+        }
+    };
+    let curve = curve
+        .or(form.curve.clone())
+        .unwrap_or_else(|| generate_curve(&form));
+    form.apply_curve(curve);
+    println!("Form: {} ({})", form.kind, form.order.join(" "));
+    if let Some(name) = rerolls
+        .iter()
+        .find(|x| !form.sections.iter().any(|s| s.name == **x))
+    {
+        return Err(anyhow!("cannot reroll unknown section {}", name));
+    }
+    if let Some(chart) = chart {
+        for section in form.sections.iter_mut().filter(|x| x.chart.is_none()) {
+            section.chart = Some(chart.clone());
+        }
+    }
+    let charts: Vec<&Chart> = form
+        .sections
+        .iter()
+        .filter_map(|x| x.chart.as_ref())
+        .collect();
+    let key = Rc::new(match charts.is_empty() {
+        true => style.key(&mut master.stream("key")),
+        false => fit_key(&charts, style.modes()),
+    });
+    let mut misfits: Vec<String> = Vec::new();
+    for symbol in charts.iter().flat_map(|x| x.misfits(&key)) {
+        if !misfits.contains(&symbol.to_string()) {
+            misfits.push(symbol.to_string());
+        }
+    }
+    if !misfits.is_empty() {
+        println!(
+            "Approximated chords that cannot be spelled in the key: {}",
+            misfits.join(" ")
+        );
+    }
+    let bpm = style.bpm(&mut master.stream("tempo"));
+    let shuffle = style.shuffle(&mut master.stream("swing"));
+    let motif = Rc::new(generate_motif(
+        &mut master.stream("motif"),
+        &style.rhythm,
+        Rational::int(style.beats as i64),
+    ));
+    let groove = style.groove(&mut master.stream("groove"));
+    let bass = [
+        BassStyle::RootFifth,
+        BassStyle::Octaves,
+        BassStyle::Kick,
+        BassStyle::Walking,
+    ][master.stream("bass").gen_range(0..4)];
+    let mut rng = master.stream("percussion");
+    let percussion = [
+        (
+            Drum::Shaker,
+            Rhythm::Euclidean {
+                hits: rng.gen_range(3..=7),
+                steps: 8,
+                rotation: rng.gen_range(0..8),
+            },
+        ),
+        (
+            Drum::Claves,
+            Rhythm::Euclidean {
+                hits: rng.gen_range(3..=5),
+                steps: [8, 12, 16][rng.gen_range(0..3)],
+                rotation: 0,
+            },
+        ),
+        (
+            Drum::Cowbell,
+            Rhythm::Polyrhythm {
+                count: 3,
+                span: [1, 2, 4][rng.gen_range(0..3)],
+            },
+        ),
+    ];
+    let counter = [
+        CounterStyle::Contrary,
+        CounterStyle::Thirds,
+        CounterStyle::Sixths,
+    ][master.stream("counter").gen_range(0..3)];
+    let voicing = Voicing::new(
+        [VoicingStyle::Closed, VoicingStyle::Open][master.stream("voicing").gen_range(0..2)],
     );
+    let mut rng = master.stream("arpeggio");
+    let arpeggio = Arpeggio::new(
+        [
+            ArpPattern::Up,
+            ArpPattern::Down,
+            ArpPattern::UpDown,
+            ArpPattern::Random,
+            ArpPattern::Pattern(vec![0, 2, 1, 2]),
+        ][rng.gen_range(0..5)]
+        .clone(),
+        [Rate::Eighths, Rate::Sixteenths, Rate::Triplets][rng.gen_range(0..3)],
+    )
+    .spanning(rng.gen_range(1..=2))
+    .gated(Rational::new(rng.gen_range(2..=4), 4))
+    .accented(&[true, false, false]);
 
-    let sec_b = generate_section(
-        &mut rng,
-        SectionSettings::new(8, key.clone(), bpm, 4, Dynamic::MezzoForte),
-    );
+    let material = Material {
+        key,
+        bpm,
+        beats: style.beats,
+        shuffle,
+        groove,
+        harmony: style.harmony.clone(),
+        voicing,
+        motif,
+        model,
+        bass,
+        percussion: percussion.to_vec(),
+        counter,
+        counterpoint: style.counterpoint.clone(),
+        arpeggio,
+        evolution,
+        rhythm: style.rhythm,
+    };
 
-    let outro = generate_section(
-        &mut rng,
-        SectionSettings::new(4, key.clone(), bpm, 4, Dynamic::MezzoPiano),
-    );
+    let registry = Registry::default();
+    let mut score = Score::default();
+    for section in form.sections.iter() {
+        let settings = material.settings(section);
+        let seed = rerolls.iter().filter(|x| **x == section.name).fold(
+            master.derive("sections").derive(&section.name),
+            |seed, _| seed.derive("reroll"),
+        );
+        let generated = match &section.variation_of {
+            Some(original) => registry.vary(
+                seed,
+                &settings,
+                score.section(original).ok_or(anyhow!(
+                    "{} varies unknown section {}",
+                    section.name,
+                    original
+                ))?,
+                &["melody", "arpeggio", "percussion"],
+            )?,
+            None => registry.generate(seed, &settings)?,
+        };
+        report_counterpoint(&section.name, &generated, &style.counterpoint);
+        score.add_section(&section.name, generated);
+    }
+    for part in form.parts() {
+        score.add_part(part)?;
+    }
 
-    let score = Score::from_sections(vec![
-        intro,
-        sec_a.clone(),
-        sec_b.clone(),
-        sec_a,
-        sec_b,
-        outro,
-    ]);
+    let names = [
+        "Lead",
+        "Strings",
+        "Bass",
+        "Drums",
+        "Percussion",
+        "Arpeggio",
+        "Counter",
+    ];
+    save_abc(
+        &score,
+        &format!("gen_{}", seed),
+        names,
+        format!("./output/gen_{}.abc", seed),
+    )?;
+    save_midi(&score, names, format!("./output/gen_{}.mid", seed))?;
+    form.save(format!("./output/gen_{}.form", seed))?;
 
     let voices = score.convert_to_playable();
+    let mut rng = master.stream("synths");
 
-    let [keys_voice, strings_voice, drums_voice, snare_voice] = voices;
+    let [keys_voice, strings_voice, bass_voice, drums_voice, percussion_voice, arpeggio_voice, counter_voice] =
+        voices;
 
-    let lead = Instrument::new(Box::new(random_lead(&mut rng, 0.65)), keys_voice);
-    let chords = Instrument::new(Box::new(strings_synth(0.95)), strings_voice);
-    let bassdrum = Instrument::new(Box::new(bassdrum_synth(1.0)), drums_voice);
-    let snare = Instrument::new(Box::new(snare_synth(1.0)), snare_voice);
+    let lead = Instrument::new(style.lead(&mut rng), keys_voice);
+    let chords = Instrument::new(style.chords(&mut rng), strings_voice);
+    let bass = Instrument::new(style.bass(&mut rng), bass_voice);
+    let volume = style.drums;
+    let drums = DrumKit::new(
+        vec![
+            (Drum::Kick.midi(), Box::new(bassdrum_synth(volume))),
+            (Drum::Snare.midi(), Box::new(snare_synth(volume))),
+            (
+                Drum::SideStick.midi(),
+                Box::new(block_synth(0.5 * volume, 1800.0)),
+            ),
+            (
+                Drum::ClosedHat.midi(),
+                Box::new(hihat_synth(0.4 * volume, false)),
+            ),
+            (
+                Drum::OpenHat.midi(),
+                Box::new(hihat_synth(0.4 * volume, true)),
+            ),
+            (Drum::Clap.midi(), Box::new(clap_synth(0.8 * volume))),
+            (Drum::LowTom.midi(), Box::new(tom_synth(0.9 * volume, 90.0))),
+            (
+                Drum::MidTom.midi(),
+                Box::new(tom_synth(0.9 * volume, 130.0)),
+            ),
+            (
+                Drum::HighTom.midi(),
+                Box::new(tom_synth(0.9 * volume, 180.0)),
+            ),
+            (
+                Drum::Crash.midi(),
+                Box::new(cymbal_synth(0.5 * volume, false)),
+            ),
+            (
+                Drum::Ride.midi(),
+                Box::new(cymbal_synth(0.4 * volume, true)),
+            ),
+        ],
+        drums_voice,
+    );
+    let volume = style.percussion;
+    let percussion = DrumKit::new(
+        vec![
+            (
+                Drum::SideStick.midi(),
+                Box::new(block_synth(0.5 * volume, 1800.0)),
+            ),
+            (
+                Drum::Cowbell.midi(),
+                Box::new(block_synth(0.35 * volume, 560.0)),
+            ),
+            (
+                Drum::Claves.midi(),
+                Box::new(block_synth(0.45 * volume, 2500.0)),
+            ),
+            (
+                Drum::Shaker.midi(),
+                Box::new(hihat_synth(0.2 * volume, false)),
+            ),
+        ],
+        percussion_voice,
+    );
+
+    let arpeggio = Instrument::new(style.arpeggio(&mut rng), arpeggio_voice);
+    let counter = Instrument::new(style.counter(&mut rng), counter_voice);
 
     let sound = SoundMix::mix(vec![
         Box::new(lead),
         Box::new(chords),
-        Box::new(bassdrum),
-        Box::new(snare),
+        Box::new(bass),
+        Box::new(drums),
+        Box::new(percussion),
+        Box::new(arpeggio),
+        Box::new(counter),
     ]);
 
     println!("Saving...");
@@ -83,3 +425,130 @@ fn run() -> Result<(), anyhow::Error> {
     playback(&sound)?;
     Ok(())
 }
+
+struct Material {
+    key: Rc<Key>,
+    bpm: f64,
+    beats: u8,
+    shuffle: bool,
+    groove: Groove,
+    harmony: Harmony,
+    voicing: Voicing,
+    motif: Rc<Motif>,
+    model: Option<Rc<MarkovModel>>,
+    bass: BassStyle,
+    percussion: Vec<(Drum, Rhythm)>,
+    counter: CounterStyle,
+    counterpoint: RuleSet,
+    arpeggio: Arpeggio,
+    evolution: Option<Evolution>,
+    rhythm: MelodyRhythm,
+}
+
+impl Material {
+    fn settings(&self, section: &FormSection) -> SectionSettings {
+        let melody = match section.role {
+            Role::Intro => Melody::Contour(Contour::Arch),
+            Role::Verse => Melody::Contour(Contour::Wave),
+            Role::Episode => Melody::Contour(Contour::Falling),
+            Role::Bridge | Role::Build => Melody::Contour(Contour::Rising),
+            Role::Chorus | Role::Drop | Role::Outro => Melody::Motif(self.motif.clone()),
+        };
+        let mut settings = SectionSettings::new(
+            section.length,
+            self.key.clone(),
+            self.bpm,
+            self.beats,
+            section.dynamic,
+            self.shuffle,
+        )
+        .with_groove(self.groove)
+        .with_harmony(self.harmony.clone())
+        .with_voicing(self.voicing)
+        .with_energy(&section.levels)
+        .with_rhythm(
+            self.rhythm
+                .with_density(self.rhythm.density() + (section.energy - 0.5) * 0.4),
+        )
+        .with_progression(
+            &section
+                .chart
+                .as_ref()
+                .map_or(Vec::new(), |x| x.progression(&self.key)),
+        )
+        .with_melody(match &self.model {
+            Some(model) => Melody::Markov(model.clone()),
+            None => melody,
+        });
+
+        settings = match section.role {
+            Role::Intro => settings,
+            Role::Chorus | Role::Drop => settings.with_range((5, 0), (6, 4)).with_bass(self.bass),
+            Role::Outro => settings
+                .with_range((4, 4), (5, 4))
+                .with_voicing(self.voicing.within((3, 4), (5, 2))),
+            _ => settings.with_bass(self.bass),
+        };
+        settings = settings.raised(((section.energy - 0.5) * 4.0).round() as i32);
+        let lead = Doubling::lead();
+        let strings = Doubling::on(1).octaves(-1);
+        settings = settings.with_orchestration(match section.role {
+            Role::Intro => Orchestration::new(),
+            Role::Verse | Role::Build => Orchestration::new().with(lead.octaves(-1)),
+            Role::Bridge | Role::Episode => {
+                Orchestration::new().with(lead.with_interval(Interval::ThirdBelow))
+            }
+            Role::Chorus | Role::Drop => Orchestration::new().with(lead.octaves(-1)).with(strings),
+            Role::Outro => Orchestration::new().with(Doubling::on(1)).resting(),
+        });
+        if section.energy >= 0.45 && section.role != Role::Outro {
+            settings = settings.with_percussion(&self.percussion);
+        }
+        if let Some(evolution) = self.evolution {
+            settings = settings.with_evolution(evolution);
+        }
+        if section.energy >= 0.6 {
+            settings = settings
+                .with_counter(self.counter)
+                .with_counterpoint(self.counterpoint.clone());
+        }
+        if section.energy >= 0.3
+            && matches!(
+                section.role,
+                Role::Verse | Role::Bridge | Role::Episode | Role::Build | Role::Outro
+            )
+        {
+            settings = settings.with_arpeggio(self.arpeggio.clone());
+        }
+        settings
+    }
+}
+
+fn report_counterpoint<const N: usize>(name: &str, section: &Section<N>, rules: &RuleSet) {
+    let violations = check(rules, section.bars(), &[0, 6, 2]);
+    if violations.is_empty() {
+        return;
+    }
+    println!("{}: {} counterpoint violations", name, violations.len());
+    for violation in violations {
+        println!("  {}", violation);
+    }
+}
+
+fn train(args: &[String]) -> Result<(), anyhow::Error> {
+    let (Some(folder), Some(path)) = (args.first(), args.get(1)) else {
+        return Err(anyhow!("Usage: musicgen train <folder> <model> [order]"));
+    };
+    let order = match args.get(2) {
+        Some(order) => order.parse()?,
+        None => 2,
+    };
+    let lines = import_folder(folder)?;
+    let model = MarkovModel::train(order, &lines);
+    if model.is_empty() {
+        return Err(anyhow!("No melodies found in {}", folder));
+    }
+    model.save(path)?;
+    println!("Trained order {} model on {} melodies", order, lines.len());
+    Ok(())
+}
@@ -2,7 +2,7 @@ use std::time::Duration;
 
 use fundsp::hacker::*;
 
-use super::synth::Synth;
+use super::{math::hz_midi, synth::Synth};
 
 pub struct Instrument {
     timer: Shared<f64>,
@@ -69,6 +69,40 @@ impl SoundMaker for Instrument {
     }
 }
 
+pub struct DrumKit {
+    pieces: Vec<Instrument>,
+}
+
+impl DrumKit {
+    pub fn new(kit: Vec<(u8, Box<dyn Synth>)>, notes: Vec<Tone>) -> Self {
+        let pieces = kit
+            .into_iter()
+            .filter_map(|(midi, synth)| {
+                let notes: Vec<Tone> = notes
+                    .iter()
+                    .filter(|x| hz_midi(x.pitch).round() as u8 == midi)
+                    .cloned()
+                    .collect();
+                (!notes.is_empty()).then(|| Instrument::new(synth, notes))
+            })
+            .collect();
+        Self { pieces }
+    }
+}
+
+impl SoundMaker for DrumKit {
+    fn build(&self) -> Sound {
+        self.pieces
+            .iter()
+            .map(|x| x.build())
+            .reduce(Sound::mix)
+            .unwrap_or(Sound(
+                Net64::wrap(Box::new(zero() | zero())),
+                Duration::ZERO,
+            ))
+    }
+}
+
 #[inline]
 fn asdr_control(time: f64, end_time: f64) -> f64 {
     if time < end_time {
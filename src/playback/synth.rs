@@ -1,7 +1,7 @@
-use fundsp::sound::{bassdrum, snaredrum};
+use fundsp::sound::{bassdrum, cymbal, snaredrum};
 use rand::{rngs::StdRng, Rng};
 
-use crate::hacker::*;
+use fundsp::hacker::*;
 
 use super::math::*;
 
@@ -26,6 +26,7 @@ pub enum Parameter {
     Const(f64),
     Enveloped(Envelope, f64, f64),
     KeyTracked((f64, f64), (f64, f64), bool),
+    VelocityTracked(f64, f64),
 }
 
 impl Parameter {
@@ -38,6 +39,13 @@ impl Parameter {
             Self::KeyTracked(in_range, out_range, is_midi) => Net64::wrap(Box::new(
                 self.keytracked(in_range, out_range, *is_midi) | multisink::<U2>(),
             )),
+            Self::VelocityTracked(low, high) => {
+                let (low, high) = (*low, *high);
+                Net64::wrap(Box::new(
+                    multisink::<U2>()
+                        | map(move |x: &Frame<f64, U1>| xerp(low, high, x[0].clamp(0.0, 1.0))),
+                ))
+            }
         }
     }
     fn keytracked(
@@ -239,11 +247,16 @@ pub struct SynthMaster {
     pub reverb_mix: f64,
     pub pan: f64,
     pub volume: f64,
+    pub brightness: Option<Filter>,
 }
 
 impl Synth for SynthMaster {
     fn instantiate(&self) -> Net64 {
-        (self.synth.instantiate()
+        let synth = match &self.brightness {
+            Some(filter) => (self.synth.instantiate() ^ filter.0.as_node()) >> lowpass_q(filter.1),
+            None => self.synth.instantiate(),
+        };
+        (synth
             >> pan(self.pan)
             >> (multipass()
                 & (self.reverb_mix * reverb_stereo(self.reverb_size, self.reverb_time))))
@@ -270,8 +283,13 @@ impl SynthMaster {
             reverb_mix,
             pan,
             volume,
+            brightness: None,
         }
     }
+    pub fn with_brightness(mut self, low: f64, high: f64) -> Self {
+        self.brightness = Some(Filter(Parameter::VelocityTracked(low, high), 0.3));
+        self
+    }
 }
 
 pub struct SynthVibrato {
@@ -398,6 +416,7 @@ pub fn keys_synth(volume: f64) -> impl Synth {
     ]);
 
     SynthMaster::new(Box::new(layerd_synth), 10.0, 5.5, 0.0, 0.0, volume)
+        .with_brightness(1500.0, 16000.0)
 }
 
 pub fn sustain_keys_synth(volume: f64) -> impl Synth {
@@ -426,6 +445,7 @@ pub fn sustain_keys_synth(volume: f64) -> impl Synth {
     ]);
 
     SynthMaster::new(Box::new(layerd_synth), 10.0, 5.5, 0.0, 0.0, volume)
+        .with_brightness(1500.0, 16000.0)
 }
 
 pub fn strings_synth(volume: f64) -> impl Synth {
@@ -449,6 +469,64 @@ pub fn strings_synth(volume: f64) -> impl Synth {
     );
 
     SynthMaster::new(Box::new(vibrato_synth), 10.0, 2.5, 1.0, 0.0, volume)
+        .with_brightness(2000.0, 16000.0)
+}
+
+pub fn bass_synth(volume: f64) -> impl Synth {
+    let synth = SimpleSynth::new(
+        Envelope(0.01, 0.6, 0.6, 0.08),
+        WaveMix::new(0.15, 0.35, 0.4, 0.1, 0.0, 0.0),
+        vec![(1.0, 0.85), (2.0, 0.15)],
+    );
+
+    let low_filter = Some(Filter(
+        Parameter::Enveloped(Envelope(0.0, 0.25, 0.2, 0.1), 400.0, 1800.0),
+        0.3,
+    ));
+    let high_filter = Some(Filter(Parameter::Const(30.0), 0.5));
+
+    let filtered_synth = SynthFilter::new(Box::new(synth), low_filter, high_filter);
+
+    SynthMaster::new(Box::new(filtered_synth), 10.0, 1.5, 0.05, 0.0, volume)
+        .with_brightness(600.0, 6000.0)
+}
+
+pub fn chip_synth(volume: f64) -> impl Synth {
+    let synth = SimpleSynth::new(
+        Envelope(0.005, 0.2, 0.7, 0.05),
+        WaveMix::new(0.6, 0.0, 0.0, 0.1, 0.3, 0.0),
+        vec![(1.0, 1.0)],
+    );
+
+    let high_filter = Some(Filter(Parameter::Const(80.0), 0.5));
+    let filtered_synth = SynthFilter::new(Box::new(synth), None, high_filter);
+
+    SynthMaster::new(Box::new(filtered_synth), 10.0, 1.0, 0.05, 0.0, volume)
+        .with_brightness(2000.0, 18000.0)
+}
+
+pub fn pad_synth(volume: f64) -> impl Synth {
+    let synth = SimpleSynth::new(
+        Envelope(1.2, 2.0, 0.7, 1.5),
+        WaveMix::new(0.0, 0.45, 0.35, 0.2, 0.0, 0.0),
+        vec![(1.0, 0.7), (0.5, 0.15), (2.0, 0.15)],
+    );
+
+    let low_filter = Some(Filter(
+        Parameter::Enveloped(Envelope(2.0, 1.0, 0.6, 1.0), 800.0, 3000.0),
+        0.2,
+    ));
+    let high_filter = Some(Filter(Parameter::Const(150.0), 0.5));
+
+    let filtered_synth = SynthFilter::new(Box::new(synth), low_filter, high_filter);
+    let vibrato_synth = SynthVibrato::new(
+        Box::new(filtered_synth),
+        Parameter::Const(0.3),
+        Parameter::Const(0.003),
+    );
+
+    SynthMaster::new(Box::new(vibrato_synth), 20.0, 6.0, 0.8, 0.0, volume)
+        .with_brightness(800.0, 8000.0)
 }
 
 pub fn random_lead(rng: &mut StdRng, volume: f64) -> impl Synth {
@@ -480,6 +558,7 @@ pub fn random_lead(rng: &mut StdRng, volume: f64) -> impl Synth {
     );
 
     SynthMaster::new(Box::new(vibrato_synth), 10.0, 2.5, 0.2, 0.0, volume)
+        .with_brightness(1500.0, 16000.0)
 }
 
 pub fn bassdrum_synth(volume: f64) -> impl Synth {
@@ -505,3 +584,60 @@ pub fn snare_synth(volume: f64) -> impl Synth {
 
     SynthMaster::new(Box::new(filtered_synth), 40.0, 4.5, 0.5, 0.0, volume)
 }
+
+pub fn tom_synth(volume: f64, pitch: f64) -> impl Synth {
+    let synth = CustomSynth::new(Envelope(0.0, 0.0, 1.0, 0.5), move || {
+        sink() | bassdrum(0.4, pitch * 2.5, pitch)
+    });
+
+    let low_filter = Some(Filter(Parameter::Const(6000.0), 0.3));
+    let filtered_synth = SynthFilter::new(Box::new(synth), low_filter, None);
+
+    SynthMaster::new(Box::new(filtered_synth), 40.0, 4.5, 0.5, 0.0, volume)
+}
+
+pub fn clap_synth(volume: f64) -> impl Synth {
+    let synth = CustomSynth::new(Envelope(0.0, 0.0, 1.0, 0.3), || {
+        let bursts = lfo(|t: f64| {
+            if t < 0.03 {
+                exp(-(t % 0.01) * 300.0)
+            } else {
+                exp(-(t - 0.03) * 25.0) * 0.6
+            }
+        });
+        sink() | ((noise() * bursts) >> bandpass_hz(1200.0, 1.5))
+    });
+
+    SynthMaster::new(Box::new(synth), 40.0, 4.5, 0.5, 0.0, volume)
+}
+
+pub fn block_synth(volume: f64, pitch: f64) -> impl Synth {
+    let synth = CustomSynth::new(Envelope(0.0, 0.0, 1.0, 0.2), move || {
+        let tone = (sine_hz(pitch) + 0.5 * sine_hz(pitch * 1.48)) * lfo(|t: f64| exp(-t * 30.0));
+        let click = noise() * lfo(|t: f64| exp(-t * 300.0)) * 0.3;
+        sink() | (tone + click)
+    });
+
+    SynthMaster::new(Box::new(synth), 40.0, 4.5, 0.4, -0.3, volume)
+}
+
+pub fn hihat_synth(volume: f64, open: bool) -> impl Synth {
+    let decay = if open { 6.0 } else { 40.0 };
+    let synth = CustomSynth::new(Envelope(0.0, 0.0, 1.0, 0.4), move || {
+        sink() | (cymbal(1) * lfo(move |t: f64| exp(-t * decay)))
+    });
+
+    let high_filter = Some(Filter(Parameter::Const(7000.0), 0.5));
+    let filtered_synth = SynthFilter::new(Box::new(synth), None, high_filter);
+
+    SynthMaster::new(Box::new(filtered_synth), 40.0, 4.5, 0.3, 0.2, volume)
+}
+
+pub fn cymbal_synth(volume: f64, ride: bool) -> impl Synth {
+    let decay = if ride { 3.0 } else { 1.0 };
+    let synth = CustomSynth::new(Envelope(0.0, 0.0, 1.0, 2.0), move || {
+        sink() | (cymbal(if ride { 4 } else { 3 }) * lfo(move |t: f64| exp(-t * decay)))
+    });
+
+    SynthMaster::new(Box::new(synth), 40.0, 4.5, 0.5, -0.2, volume)
+}
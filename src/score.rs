@@ -1,27 +1,137 @@
+pub mod rational;
+
 use std::rc::Rc;
 
+use anyhow::anyhow;
+
 use crate::playback::instrument::Tone;
 
-#[derive(Debug, Clone)]
+pub use self::rational::Rational;
+
+#[derive(Debug, Clone, Default)]
 pub struct Score<const N: usize> {
-    sections: Vec<Section<N>>,
+    sections: Vec<(String, Section<N>)>,
+    structure: Vec<Part>,
 }
 
 impl<const N: usize> Score<N> {
-    pub fn from_sections(sections: Vec<Section<N>>) -> Self {
-        Self { sections }
+    pub fn add_section(&mut self, name: &str, section: Section<N>) {
+        self.sections.push((name.to_string(), section));
+    }
+    pub fn add_part(&mut self, part: Part) -> Result<(), anyhow::Error> {
+        if let Some(name) = part
+            .sections
+            .iter()
+            .chain(part.endings.iter().flatten())
+            .find(|x| self.section(x).is_none())
+        {
+            return Err(anyhow!("part uses unknown section {}", name));
+        }
+        if part.endings.len() > part.repeats {
+            return Err(anyhow!(
+                "part has {} endings but only {} repeats",
+                part.endings.len(),
+                part.repeats
+            ));
+        }
+        self.structure.push(part);
+        Ok(())
+    }
+    pub fn section(&self, name: &str) -> Option<&Section<N>> {
+        self.sections.iter().find(|x| x.0 == name).map(|x| &x.1)
+    }
+    pub fn parts(&self) -> &[Part] {
+        &self.structure
+    }
+    pub fn expand(&self) -> Vec<(&str, &Section<N>)> {
+        self.structure
+            .iter()
+            .flat_map(|part| part.expand())
+            .filter_map(|name| Some((name, self.section(name)?)))
+            .collect()
     }
     pub fn convert_to_playable(&self) -> [Vec<Tone>; N] {
+        let sections = self.expand();
         std::array::from_fn(|i| {
             let mut time = 0.0;
-            self.sections
+            let tones = sections
                 .iter()
-                .flat_map(|x| x.convert_to_playable(i, &mut time))
-                .collect()
+                .flat_map(|x| x.1.convert_to_playable(i, &mut time))
+                .collect();
+            merge_ties(tones)
         })
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Part {
+    pub sections: Vec<String>,
+    pub repeats: usize,
+    pub endings: Vec<Vec<String>>,
+    pub mark: Option<String>,
+}
+
+impl Part {
+    pub fn new(sections: &[&str]) -> Self {
+        Self {
+            sections: sections.iter().map(|x| x.to_string()).collect(),
+            repeats: 1,
+            endings: Vec::new(),
+            mark: None,
+        }
+    }
+    pub fn repeated(mut self, repeats: usize) -> Self {
+        self.repeats = repeats.max(1);
+        self
+    }
+    pub fn with_endings(mut self, endings: &[&[&str]]) -> Self {
+        self.endings = endings
+            .iter()
+            .map(|x| x.iter().map(|x| x.to_string()).collect())
+            .collect();
+        self
+    }
+    pub fn marked(mut self, mark: &str) -> Self {
+        self.mark = Some(mark.to_string());
+        self
+    }
+    pub fn ending(&self, pass: usize) -> Option<usize> {
+        match self.endings.len() {
+            0 => None,
+            len if pass + 1 == self.repeats => Some(len - 1),
+            len => Some(pass.min(len.saturating_sub(2))),
+        }
+    }
+    fn expand(&self) -> Vec<&str> {
+        (0..self.repeats)
+            .flat_map(|pass| {
+                let ending = self.ending(pass).map_or(&[][..], |x| &self.endings[x]);
+                self.sections.iter().chain(ending).map(|x| x.as_str())
+            })
+            .collect()
+    }
+}
+
+fn merge_ties(mut tones: Vec<(Tone, bool)>) -> Vec<Tone> {
+    tones.sort_by(|a, b| a.0.start_time.total_cmp(&b.0.start_time));
+    let mut merged: Vec<(Tone, bool)> = Vec::new();
+    for (tone, tie) in tones {
+        let open = merged.iter_mut().find(|(x, open)| {
+            *open
+                && x.pitch == tone.pitch
+                && (x.start_time + x.duration - tone.start_time).abs() < 1e-6
+        });
+        match open {
+            Some((x, open)) => {
+                x.duration += tone.duration;
+                *open = tie;
+            }
+            None => merged.push((tone, tie)),
+        }
+    }
+    merged.into_iter().map(|x| x.0).collect()
+}
+
 #[derive(Debug, Clone)]
 pub struct Section<const N: usize> {
     bars: Vec<Bar<N>>,
@@ -31,7 +141,10 @@ impl<const N: usize> Section<N> {
     pub fn from_bars(bars: Vec<Bar<N>>) -> Self {
         Self { bars }
     }
-    pub fn convert_to_playable(&self, voice: usize, time: &mut f64) -> Vec<Tone> {
+    pub fn bars(&self) -> &[Bar<N>] {
+        &self.bars
+    }
+    pub fn convert_to_playable(&self, voice: usize, time: &mut f64) -> Vec<(Tone, bool)> {
         self.bars
             .iter()
             .flat_map(|x| x.convert_to_playable(voice, time))
@@ -42,10 +155,13 @@ impl<const N: usize> Section<N> {
 #[derive(Debug, Clone)]
 pub struct Bar<const N: usize> {
     pub beats: u8,
-    pub notes: [Vec<(f64, Note)>; N],
+    pub notes: [Vec<(Rational, Note)>; N],
+    pub tuplets: [Vec<Tuplet>; N],
     pub bpm: f64,
     pub key: Rc<Key>,
     pub dynamic: Dynamic,
+    pub energy: f64,
+    pub chord: Option<Chord>,
 }
 
 impl<const N: usize> Bar<N> {
@@ -53,72 +169,336 @@ impl<const N: usize> Bar<N> {
         Self {
             beats,
             notes: vec![Vec::new(); N].try_into().unwrap(),
+            tuplets: vec![Vec::new(); N].try_into().unwrap(),
             bpm,
             key,
             dynamic,
+            energy: 0.5,
+            chord: None,
         }
     }
-    pub fn add_note(&mut self, voice: usize, beat: f64, note: Note) {
+    pub fn accidental(&self, pitch: u8) -> Option<bool> {
+        self.chord.as_ref().and_then(|x| x.accidental(pitch))
+    }
+    pub fn add_note(&mut self, voice: usize, beat: Rational, note: Note) {
         self.notes[voice].push((beat, note))
     }
-    fn convert_to_playable(&self, voice: usize, time: &mut f64) -> Vec<Tone> {
+    pub fn add_tuplet(&mut self, voice: usize, tuplet: Tuplet) {
+        self.tuplets[voice].push(tuplet)
+    }
+    pub fn length(&self) -> Rational {
+        Rational::int(self.beats as i64)
+    }
+    fn convert_to_playable(&self, voice: usize, time: &mut f64) -> Vec<(Tone, bool)> {
         let tones = self.notes[voice]
             .iter()
-            .map(|(offset, x)| x.convert_to_playable(*time, self, *offset))
+            .map(|(offset, x)| (x.convert_to_playable(*time, self, *offset), x.tie))
             .collect();
         *time += self.beats as f64 * 60.0 / self.bpm;
         tones
     }
 }
 
+#[derive(Debug, Clone)]
+pub struct Tuplet {
+    pub start: Rational,
+    pub span: Rational,
+    pub count: u8,
+}
+
+impl Tuplet {
+    pub fn new(start: Rational, span: Rational, count: u8) -> Self {
+        Self { start, span, count }
+    }
+    pub fn triplet(start: Rational, span: Rational) -> Self {
+        Self::new(start, span, 3)
+    }
+    pub fn unit(&self) -> Rational {
+        self.span / self.count as i64
+    }
+    pub fn offset(&self, index: u8) -> Rational {
+        self.start + self.unit() * index as i64
+    }
+    pub fn end(&self) -> Rational {
+        self.start + self.span
+    }
+    pub fn normal(&self) -> u8 {
+        let mut normal = 1;
+        while normal * 2 < self.count {
+            normal *= 2;
+        }
+        normal
+    }
+}
+
 #[derive(Debug, Clone)]
 pub struct Note {
-    pub length: f64,
+    pub length: Rational,
     pub pitch: u8,
     pub octave: u8,
     pub accidental: Option<bool>,
+    pub tie: bool,
+    pub drum: Option<Drum>,
+    pub accent: bool,
+    pub doubling: bool,
 }
 
 impl Note {
-    pub fn new(length: f64, pitch: u8, octave: u8, accidental: Option<bool>) -> Self {
+    pub fn new(length: Rational, pitch: u8, octave: u8, accidental: Option<bool>) -> Self {
         Self {
             length,
             pitch: pitch % 7,
             octave,
             accidental,
+            tie: false,
+            drum: None,
+            accent: false,
+            doubling: false,
+        }
+    }
+    pub fn hit(length: Rational, drum: Drum) -> Self {
+        Self {
+            drum: Some(drum),
+            ..Self::new(length, 0, 0, None)
         }
     }
-    fn convert_to_playable<const N: usize>(&self, time: f64, bar: &Bar<N>, offset: f64) -> Tone {
+    pub fn midi(&self, key: &Key) -> f64 {
+        match self.drum {
+            Some(drum) => drum.midi() as f64,
+            None => key.midi(self),
+        }
+    }
+    pub fn velocity(&self, dynamic: Dynamic, energy: f64) -> f64 {
+        let velocity = dynamic.velocity() * (1.0 + (energy - 0.5) * 0.3);
+        if self.accent {
+            (velocity * 1.25).min(127.0)
+        } else {
+            velocity.min(127.0)
+        }
+    }
+    fn convert_to_playable<const N: usize>(
+        &self,
+        time: f64,
+        bar: &Bar<N>,
+        offset: Rational,
+    ) -> Tone {
         let secs_per_beat = 60.0 / bar.bpm;
-        let time_offset = offset * secs_per_beat;
+        let time_offset = offset.as_f64() * secs_per_beat;
         Tone::midi(
             time + time_offset,
-            self.length * secs_per_beat,
-            bar.key.midi(self),
-            bar.dynamic.velocity(),
+            self.length.as_f64() * secs_per_beat,
+            self.midi(&bar.key),
+            self.velocity(bar.dynamic, bar.energy),
         )
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum Drum {
+    Kick,
+    Snare,
+    ClosedHat,
+    OpenHat,
+    Clap,
+    LowTom,
+    MidTom,
+    HighTom,
+    Crash,
+    Ride,
+    SideStick,
+    Cowbell,
+    Claves,
+    Shaker,
+}
+
+impl Drum {
+    pub fn midi(&self) -> u8 {
+        match self {
+            Self::Kick => 36,
+            Self::Snare => 38,
+            Self::ClosedHat => 42,
+            Self::OpenHat => 46,
+            Self::Clap => 39,
+            Self::LowTom => 45,
+            Self::MidTom => 47,
+            Self::HighTom => 50,
+            Self::Crash => 49,
+            Self::Ride => 51,
+            Self::SideStick => 37,
+            Self::Cowbell => 56,
+            Self::Claves => 75,
+            Self::Shaker => 70,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Function {
+    Tonic,
+    Predominant,
+    Dominant,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Chord {
+    pub degree: u8,
+    pub seventh: bool,
+    accidentals: [Option<bool>; 4],
+    bass: Option<(u8, Option<bool>)>,
+}
+
+impl Chord {
+    pub fn new(degree: u8, seventh: bool) -> Self {
+        Self {
+            degree: degree % 7,
+            seventh,
+            accidentals: [None; 4],
+            bass: None,
+        }
+    }
+    pub fn altered(mut self, accidentals: [Option<bool>; 4]) -> Self {
+        self.accidentals = accidentals;
+        self
+    }
+    pub fn over(mut self, degree: u8, accidental: Option<bool>) -> Self {
+        self.bass = Some((degree % 7, accidental));
+        self
+    }
+    pub fn bass(&self) -> (u8, Option<bool>) {
+        self.bass.unwrap_or((self.degree, self.accidentals[0]))
+    }
+    pub fn accidental(&self, pitch: u8) -> Option<bool> {
+        match self.tones().iter().position(|&x| x == pitch % 7) {
+            Some(i) => self.accidentals[i],
+            None => self.bass.filter(|x| x.0 == pitch % 7).and_then(|x| x.1),
+        }
+    }
+    pub fn function(&self) -> Function {
+        match self.degree {
+            1 | 3 => Function::Predominant,
+            4 | 6 => Function::Dominant,
+            _ => Function::Tonic,
+        }
+    }
+    pub fn tones(&self) -> Vec<u8> {
+        let intervals: &[u8] = if self.seventh {
+            &[0, 2, 4, 6]
+        } else {
+            &[0, 2, 4]
+        };
+        intervals.iter().map(|x| (self.degree + x) % 7).collect()
+    }
+    pub fn contains(&self, pitch: u8) -> bool {
+        self.tones().contains(&(pitch % 7))
+    }
+    pub fn numeral(&self, key: &Key) -> String {
+        const NUMERALS: [&str; 7] = ["I", "II", "III", "IV", "V", "VI", "VII"];
+        let numeral = NUMERALS[self.degree as usize];
+        let offset = |i: usize| self.accidentals[i].map_or(0, |x| if x { 1 } else { -1 });
+        let interval = |i: usize| key.interval(self.degree, 2 * i as u8) + offset(i) - offset(0);
+        let sign = |accidental: Option<bool>| match accidental {
+            Some(true) => "#",
+            Some(false) => "b",
+            None => "",
+        };
+        let mut name = sign(self.accidentals[0]).to_string();
+        if interval(1) == 4 {
+            name.push_str(numeral);
+        } else {
+            name.push_str(&numeral.to_lowercase());
+        }
+        match interval(2) {
+            6 => name.push('o'),
+            8 => name.push('+'),
+            _ => (),
+        }
+        if self.seventh {
+            name.push('7');
+        }
+        if let Some((degree, accidental)) = self.bass {
+            name.push_str(&format!(
+                "/{}{}",
+                sign(accidental),
+                NUMERALS[degree as usize]
+            ));
+        }
+        name
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Mode {
+    Major,
+    Minor,
+    Dorian,
+    Phrygian,
+    Lydian,
+    Mixolydian,
+}
+
+impl Mode {
+    pub const ALL: [Self; 6] = [
+        Self::Major,
+        Self::Minor,
+        Self::Dorian,
+        Self::Phrygian,
+        Self::Lydian,
+        Self::Mixolydian,
+    ];
+    fn scale(&self) -> [i8; 7] {
+        match self {
+            Self::Major => [0, 2, 4, 5, 7, 9, 11],
+            Self::Minor => [0, 2, 3, 5, 7, 8, 11],
+            Self::Dorian => [0, 2, 3, 5, 7, 9, 10],
+            Self::Phrygian => [0, 1, 3, 5, 7, 8, 10],
+            Self::Lydian => [0, 2, 4, 6, 7, 9, 11],
+            Self::Mixolydian => [0, 2, 4, 5, 7, 9, 10],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub struct Key {
     tonic: i8,
     scale: [i8; 7],
 }
 
 impl Key {
-    pub fn new(tonic: i8, mode: bool) -> Self {
+    pub fn new(tonic: i8, major: bool) -> Self {
+        Self::modal(tonic, if major { Mode::Major } else { Mode::Minor })
+    }
+    pub fn modal(tonic: i8, mode: Mode) -> Self {
         Self {
             tonic,
-            scale: Self::gen_scale(mode),
+            scale: mode.scale(),
         }
     }
-    fn gen_scale(mode: bool) -> [i8; 7] {
-        if mode {
-            [0, 2, 4, 5, 7, 9, 11]
-        } else {
-            [0, 2, 3, 5, 7, 8, 11]
-        }
+    pub fn interval(&self, degree: u8, steps: u8) -> i8 {
+        let from = self.scale[(degree % 7) as usize];
+        let to = self.scale[((degree + steps) % 7) as usize];
+        (to - from).rem_euclid(12)
+    }
+    pub fn mode(&self) -> Mode {
+        Mode::ALL
+            .into_iter()
+            .find(|x| x.scale() == self.scale)
+            .unwrap_or(Mode::Major)
+    }
+    pub fn pitch_class(&self, degree: u8) -> i8 {
+        (self.tonic + self.scale[(degree % 7) as usize]).rem_euclid(12)
+    }
+    pub fn note(&self, midi: u8, length: Rational) -> Note {
+        let relative = midi as i8 - self.tonic;
+        let octave = relative.div_euclid(12) as u8;
+        let class = relative.rem_euclid(12);
+        let (pitch, accidental) = match self.scale.iter().position(|&x| x == class) {
+            Some(pitch) => (pitch, None),
+            None => (
+                self.scale.iter().rposition(|&x| x < class).unwrap_or(0),
+                Some(true),
+            ),
+        };
+        Note::new(length, pitch as u8, octave, accidental)
     }
     fn midi(&self, note: &Note) -> f64 {
         let octave = (note.octave * 12) as i8;
@@ -127,7 +507,7 @@ impl Key {
     }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
 pub enum Dynamic {
     Piano,
     MezzoPiano,
@@ -136,7 +516,7 @@ pub enum Dynamic {
 }
 
 impl Dynamic {
-    fn velocity(&self) -> f64 {
+    pub fn velocity(&self) -> f64 {
         match self {
             Self::Piano => 52.0,
             Self::MezzoPiano => 77.0,
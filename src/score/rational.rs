@@ -0,0 +1,191 @@
+use std::cmp::Ordering;
+use std::fmt;
+use std::ops::{Add, AddAssign, Div, Mul, Neg, Sub, SubAssign};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Rational {
+    num: i64,
+    den: i64,
+}
+
+impl Rational {
+    pub const ZERO: Self = Self { num: 0, den: 1 };
+    pub const ONE: Self = Self { num: 1, den: 1 };
+
+    pub fn new(num: i64, den: i64) -> Self {
+        assert!(den != 0, "rational with zero denominator");
+        let sign = if den < 0 { -1 } else { 1 };
+        let divisor = gcd(num.abs(), den.abs()).max(1);
+        Self {
+            num: sign * num / divisor,
+            den: sign * den / divisor,
+        }
+    }
+    pub fn int(value: i64) -> Self {
+        Self { num: value, den: 1 }
+    }
+    pub fn num(&self) -> i64 {
+        self.num
+    }
+    pub fn den(&self) -> i64 {
+        self.den
+    }
+    pub fn as_f64(&self) -> f64 {
+        self.num as f64 / self.den as f64
+    }
+    pub fn floor(&self) -> i64 {
+        self.num.div_euclid(self.den)
+    }
+    pub fn fract(&self) -> Self {
+        *self - Self::int(self.floor())
+    }
+}
+
+fn gcd(a: i64, b: i64) -> i64 {
+    if b == 0 {
+        a
+    } else {
+        gcd(b, a % b)
+    }
+}
+
+impl Default for Rational {
+    fn default() -> Self {
+        Self::ZERO
+    }
+}
+
+impl From<i64> for Rational {
+    fn from(value: i64) -> Self {
+        Self::int(value)
+    }
+}
+
+impl PartialOrd for Rational {
+    fn partial_cmp(&self, other: &Self) -> Option<Ordering> {
+        Some(self.cmp(other))
+    }
+}
+
+impl Ord for Rational {
+    fn cmp(&self, other: &Self) -> Ordering {
+        (self.num as i128 * other.den as i128).cmp(&(other.num as i128 * self.den as i128))
+    }
+}
+
+impl Add for Rational {
+    type Output = Self;
+    fn add(self, rhs: Self) -> Self {
+        Self::new(self.num * rhs.den + rhs.num * self.den, self.den * rhs.den)
+    }
+}
+
+impl Sub for Rational {
+    type Output = Self;
+    fn sub(self, rhs: Self) -> Self {
+        self + -rhs
+    }
+}
+
+impl Neg for Rational {
+    type Output = Self;
+    fn neg(self) -> Self {
+        Self {
+            num: -self.num,
+            den: self.den,
+        }
+    }
+}
+
+impl Mul for Rational {
+    type Output = Self;
+    fn mul(self, rhs: Self) -> Self {
+        Self::new(self.num * rhs.num, self.den * rhs.den)
+    }
+}
+
+impl Div for Rational {
+    type Output = Self;
+    fn div(self, rhs: Self) -> Self {
+        Self::new(self.num * rhs.den, self.den * rhs.num)
+    }
+}
+
+impl Mul<i64> for Rational {
+    type Output = Self;
+    fn mul(self, rhs: i64) -> Self {
+        Self::new(self.num * rhs, self.den)
+    }
+}
+
+impl Div<i64> for Rational {
+    type Output = Self;
+    fn div(self, rhs: i64) -> Self {
+        self / Self::int(rhs)
+    }
+}
+
+impl AddAssign for Rational {
+    fn add_assign(&mut self, rhs: Self) {
+        *self = *self + rhs;
+    }
+}
+
+impl SubAssign for Rational {
+    fn sub_assign(&mut self, rhs: Self) {
+        *self = *self - rhs;
+    }
+}
+
+impl fmt::Display for Rational {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        if self.den == 1 {
+            write!(f, "{}", self.num)
+        } else {
+            write!(f, "{}/{}", self.num, self.den)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn normalises_sign_and_common_factors() {
+        assert_eq!(Rational::new(2, 4), Rational::new(1, 2));
+        let negative = Rational::new(3, -6);
+        assert_eq!((negative.num(), negative.den()), (-1, 2));
+        let zero = Rational::new(0, -5);
+        assert_eq!((zero.num(), zero.den()), (0, 1));
+        assert_eq!(
+            Rational::new(1, 3) + Rational::new(1, 6),
+            Rational::new(1, 2)
+        );
+    }
+
+    #[test]
+    fn orders_by_value() {
+        assert!(Rational::new(1, 3) < Rational::new(1, 2));
+        assert!(Rational::new(-1, 2) < Rational::ZERO);
+        assert!(Rational::new(7, 4) > Rational::ONE);
+        assert_eq!(
+            Rational::new(2, 4).cmp(&Rational::new(1, 2)),
+            Ordering::Equal
+        );
+    }
+
+    #[test]
+    fn fract_stays_in_unit_interval() {
+        assert_eq!(Rational::new(7, 4).fract(), Rational::new(3, 4));
+        assert_eq!(Rational::int(3).fract(), Rational::ZERO);
+        assert_eq!(Rational::new(-1, 4).floor(), -1);
+        assert_eq!(Rational::new(-1, 4).fract(), Rational::new(3, 4));
+    }
+
+    #[test]
+    #[should_panic(expected = "zero denominator")]
+    fn rejects_zero_denominator() {
+        Rational::new(1, 0);
+    }
+}
@@ -0,0 +1,320 @@
+use std::path::Path;
+
+use anyhow::anyhow;
+use rand::{rngs::StdRng, Rng};
+
+use crate::{
+    generation::{counterpoint::RuleSet, drums::Groove, harmony::Harmony, rhythm::MelodyRhythm},
+    playback::synth::*,
+    score::{Key, Mode},
+};
+
+const PRESETS: [(&str, &str); 6] = [
+    ("lofi", include_str!("../styles/lofi.style")),
+    ("chiptune", include_str!("../styles/chiptune.style")),
+    ("ambient", include_str!("../styles/ambient.style")),
+    ("synthwave", include_str!("../styles/synthwave.style")),
+    ("march", include_str!("../styles/march.style")),
+    ("waltz", include_str!("../styles/waltz.style")),
+];
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Patch {
+    Lead,
+    Keys,
+    SustainKeys,
+    Strings,
+    Bass,
+    Chip,
+    Pad,
+}
+
+impl Patch {
+    fn parse(name: &str) -> Result<Self, anyhow::Error> {
+        Ok(match name {
+            "lead" => Self::Lead,
+            "keys" => Self::Keys,
+            "sustain_keys" => Self::SustainKeys,
+            "strings" => Self::Strings,
+            "bass" => Self::Bass,
+            "chip" => Self::Chip,
+            "pad" => Self::Pad,
+            _ => return Err(anyhow!("unknown patch {}", name)),
+        })
+    }
+    fn synth(&self, rng: &mut StdRng, volume: f64) -> Box<dyn Synth> {
+        match self {
+            Self::Lead => Box::new(random_lead(rng, volume)),
+            Self::Keys => Box::new(keys_synth(volume)),
+            Self::SustainKeys => Box::new(sustain_keys_synth(volume)),
+            Self::Strings => Box::new(strings_synth(volume)),
+            Self::Bass => Box::new(bass_synth(volume)),
+            Self::Chip => Box::new(chip_synth(volume)),
+            Self::Pad => Box::new(pad_synth(volume)),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Style {
+    pub name: String,
+    modes: Vec<Mode>,
+    tempo: (u32, u32),
+    pub beats: u8,
+    swing: f64,
+    grooves: Vec<Groove>,
+    pub harmony: Harmony,
+    pub counterpoint: RuleSet,
+    pub rhythm: MelodyRhythm,
+    lead: (Patch, f64),
+    chords: (Patch, f64),
+    bass: (Patch, f64),
+    arpeggio: (Patch, f64),
+    counter: (Patch, f64),
+    pub drums: f64,
+    pub percussion: f64,
+}
+
+impl Default for Style {
+    fn default() -> Self {
+        Self {
+            name: String::from("default"),
+            modes: vec![Mode::Major, Mode::Minor],
+            tempo: (90, 130),
+            beats: 4,
+            swing: 0.25,
+            grooves: vec![
+                Groove::Rock,
+                Groove::FourOnTheFloor,
+                Groove::Breakbeat,
+                Groove::HalfTime,
+                Groove::Trap,
+            ],
+            harmony: Harmony::default(),
+            counterpoint: RuleSet::pop(),
+            rhythm: MelodyRhythm::default(),
+            lead: (Patch::Lead, 0.65),
+            chords: (Patch::Strings, 0.95),
+            bass: (Patch::Bass, 0.8),
+            arpeggio: (Patch::Keys, 0.4),
+            counter: (Patch::SustainKeys, 0.4),
+            drums: 1.0,
+            percussion: 1.0,
+        }
+    }
+}
+
+impl Style {
+    pub fn parse(text: &str) -> Result<Self, anyhow::Error> {
+        let mut style = Self::default();
+        let mut degrees: Option<Vec<u8>> = None;
+        let mut sevenths = 1.0;
+        for line in text.lines() {
+            let line = line.split('#').next().unwrap_or("").trim();
+            let Some((field, value)) = line.split_once(char::is_whitespace) else {
+                if line.is_empty() {
+                    continue;
+                }
+                return Err(anyhow!("missing value in style line {}", line));
+            };
+            let values: Vec<&str> = value.split_whitespace().collect();
+            match field {
+                "name" => style.name = value.trim().to_string(),
+                "modes" => {
+                    style.modes = values
+                        .iter()
+                        .map(|x| parse_mode(x))
+                        .collect::<Result<_, _>>()?
+                }
+                "tempo" => style.tempo = (number(&values, 0)?, number(&values, 1)?),
+                "meter" => style.beats = number(&values, 0)?,
+                "swing" => style.swing = number(&values, 0)?,
+                "grooves" => {
+                    style.grooves = values
+                        .iter()
+                        .map(|x| parse_groove(x))
+                        .collect::<Result<_, _>>()?
+                }
+                "harmony" => {
+                    degrees = Some(values.iter().map(|x| x.parse()).collect::<Result<_, _>>()?)
+                }
+                "sevenths" => sevenths = number(&values, 0)?,
+                "counterpoint" => style.counterpoint = rule_set(&values)?,
+                "rhythm" => style.rhythm = rhythm(&values)?,
+                "lead" => style.lead = instrument(&values)?,
+                "chords" => style.chords = instrument(&values)?,
+                "bass" => style.bass = instrument(&values)?,
+                "arpeggio" => style.arpeggio = instrument(&values)?,
+                "counter" => style.counter = instrument(&values)?,
+                "drums" => style.drums = number(&values, 0)?,
+                "percussion" => style.percussion = number(&values, 0)?,
+                _ => return Err(anyhow!("unknown style field {}", field)),
+            }
+        }
+        if style.modes.is_empty() || style.grooves.is_empty() {
+            return Err(anyhow!("style {} needs modes and grooves", style.name));
+        }
+        if style.tempo.0 > style.tempo.1 || style.beats == 0 {
+            return Err(anyhow!(
+                "style {} has an invalid tempo or meter",
+                style.name
+            ));
+        }
+        if let Some(groove) = style.grooves.iter().find(|x| !x.fits(style.beats)) {
+            return Err(anyhow!(
+                "style {} uses groove {:?}, which does not fit {} beats",
+                style.name,
+                groove,
+                style.beats
+            ));
+        }
+        style.harmony = Harmony::new(&degrees.unwrap_or((0..7).collect()), sevenths);
+        Ok(style)
+    }
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, anyhow::Error> {
+        Self::parse(&std::fs::read_to_string(path)?)
+    }
+    pub fn preset(name: &str) -> Option<Self> {
+        if name == "default" {
+            return Some(Self::default());
+        }
+        PRESETS
+            .iter()
+            .find(|x| x.0 == name)
+            .map(|x| Self::parse(x.1).expect("built-in style should parse"))
+    }
+    pub fn find(name: &str) -> Result<Self, anyhow::Error> {
+        match Self::preset(name) {
+            Some(style) => Ok(style),
+            None if Path::new(name).exists() => Self::load(name),
+            None => {
+                let names: Vec<&str> = std::iter::once("default")
+                    .chain(PRESETS.iter().map(|x| x.0))
+                    .collect();
+                Err(anyhow!(
+                    "unknown style {}, expected a file or one of: {}",
+                    name,
+                    names.join(", ")
+                ))
+            }
+        }
+    }
+    pub fn random(rng: &mut StdRng) -> Self {
+        let (name, _) = PRESETS[rng.gen_range(0..PRESETS.len())];
+        Self::preset(name).unwrap()
+    }
+    pub fn modes(&self) -> &[Mode] {
+        &self.modes
+    }
+    pub fn key(&self, rng: &mut StdRng) -> Key {
+        let mode = self.modes[rng.gen_range(0..self.modes.len())];
+        Key::modal(rng.gen_range(0..=4), mode)
+    }
+    pub fn bpm(&self, rng: &mut StdRng) -> f64 {
+        rng.gen_range(self.tempo.0..=self.tempo.1) as f64
+    }
+    pub fn shuffle(&self, rng: &mut StdRng) -> bool {
+        rng.gen_bool(self.swing.clamp(0.0, 1.0))
+    }
+    pub fn groove(&self, rng: &mut StdRng) -> Groove {
+        self.grooves[rng.gen_range(0..self.grooves.len())]
+    }
+    pub fn lead(&self, rng: &mut StdRng) -> Box<dyn Synth> {
+        self.lead.0.synth(rng, self.lead.1)
+    }
+    pub fn chords(&self, rng: &mut StdRng) -> Box<dyn Synth> {
+        self.chords.0.synth(rng, self.chords.1)
+    }
+    pub fn bass(&self, rng: &mut StdRng) -> Box<dyn Synth> {
+        self.bass.0.synth(rng, self.bass.1)
+    }
+    pub fn arpeggio(&self, rng: &mut StdRng) -> Box<dyn Synth> {
+        self.arpeggio.0.synth(rng, self.arpeggio.1)
+    }
+    pub fn counter(&self, rng: &mut StdRng) -> Box<dyn Synth> {
+        self.counter.0.synth(rng, self.counter.1)
+    }
+}
+
+fn number<T: std::str::FromStr>(values: &[&str], index: usize) -> Result<T, anyhow::Error> {
+    values
+        .get(index)
+        .and_then(|x| x.parse().ok())
+        .ok_or(anyhow!("invalid style value {}", values.join(" ")))
+}
+
+fn instrument(values: &[&str]) -> Result<(Patch, f64), anyhow::Error> {
+    let patch = Patch::parse(values.first().copied().unwrap_or(""))?;
+    Ok((patch, number(values, 1)?))
+}
+
+fn rule_set(values: &[&str]) -> Result<RuleSet, anyhow::Error> {
+    let rules = match values.first().copied() {
+        Some("strict") => RuleSet::strict(),
+        Some("pop") => RuleSet::pop(),
+        _ => return Err(anyhow!("unknown rule set {}", values.join(" "))),
+    };
+    match values.get(1) {
+        Some(_) => Ok(rules.with_attempts(number(values, 1)?)),
+        None => Ok(rules),
+    }
+}
+
+fn rhythm(values: &[&str]) -> Result<MelodyRhythm, anyhow::Error> {
+    let mut rhythm = MelodyRhythm::new(number(values, 0)?);
+    if values.len() > 1 {
+        rhythm = rhythm.syncopated(number(values, 1)?);
+    }
+    if values.len() > 2 {
+        rhythm = rhythm.with_sixteenths(number(values, 2)?);
+    }
+    if values.len() > 3 {
+        rhythm = rhythm.with_rests(number(values, 3)?);
+    }
+    Ok(rhythm)
+}
+
+fn parse_mode(name: &str) -> Result<Mode, anyhow::Error> {
+    Ok(match name {
+        "major" => Mode::Major,
+        "minor" => Mode::Minor,
+        "dorian" => Mode::Dorian,
+        "phrygian" => Mode::Phrygian,
+        "lydian" => Mode::Lydian,
+        "mixolydian" => Mode::Mixolydian,
+        _ => return Err(anyhow!("unknown mode {}", name)),
+    })
+}
+
+fn parse_groove(name: &str) -> Result<Groove, anyhow::Error> {
+    Ok(match name {
+        "rock" => Groove::Rock,
+        "four_on_the_floor" => Groove::FourOnTheFloor,
+        "breakbeat" => Groove::Breakbeat,
+        "half_time" => Groove::HalfTime,
+        "trap" => Groove::Trap,
+        "march" => Groove::March,
+        "waltz" => Groove::Waltz,
+        _ => return Err(anyhow!("unknown groove {}", name)),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_every_preset() {
+        for (name, _) in PRESETS.iter() {
+            assert_eq!(Style::preset(name).unwrap().name, *name);
+        }
+        assert_eq!(Style::find("default").unwrap().name, "default");
+    }
+
+    #[test]
+    fn rejects_grooves_that_do_not_fit_the_meter() {
+        assert!(Style::parse("meter 3\ngrooves waltz").is_ok());
+        assert!(Style::parse("meter 3\ngrooves waltz rock").is_err());
+        assert!(Style::parse("meter 0\ngrooves rock").is_err());
+    }
+}